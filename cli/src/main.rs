@@ -0,0 +1,77 @@
+use dawnorm::scaffold::Scaffolder;
+use tokio_postgres::NoTls;
+
+const USAGE: &str = "usage: dawnorm scaffold <connection-string> [--schema <schema>] [--context <name>] [--output <file>]";
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    match args.first().map(|x| x.as_str()) {
+        Some("scaffold") => scaffold(&args[1..]).await,
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn scaffold(args: &[String]) {
+    let mut connection_string = None;
+    let mut output = None;
+    let mut scaffolder = Scaffolder::new();
+
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        match arg.as_str() {
+            "--schema" => scaffolder = scaffolder.schema(expect_value(iter.next())),
+            "--context" => scaffolder = scaffolder.context_name(expect_value(iter.next())),
+            "--output" => output = Some(expect_value(iter.next()).to_string()),
+            x => connection_string = Some(x.to_string()),
+        }
+    }
+
+    let connection_string = expect_value(connection_string.as_ref());
+    let (client, connection) = match tokio_postgres::connect(connection_string, NoTls).await {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("connection error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    tokio::spawn(async move {
+        if let Err(e) = connection.await {
+            eprintln!("connection error: {}", e);
+        }
+    });
+
+    let tables = match scaffolder.introspect(&client).await {
+        Ok(x) => x,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let code = scaffolder.generate(&tables);
+    match output {
+        Some(path) => {
+            if let Err(e) = std::fs::write(&path, code) {
+                eprintln!("can't write {}: {}", path, e);
+                std::process::exit(1);
+            }
+        },
+        None => print!("{}", code),
+    }
+}
+
+fn expect_value(value: Option<&String>) -> &str {
+    match value {
+        Some(x) => x,
+        None => {
+            eprintln!("{}", USAGE);
+            std::process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,263 @@
+use proc_macro2::Span;
+use syn::{parse::{Parse, ParseStream}, punctuated::Punctuated, Attribute, Field, Ident, LitStr, Path, Token, Type};
+
+/*
+ * #[dawnorm(rename = "column")]
+ * #[dawnorm(default)]         Default::default() when the column is missing or NULL
+ * #[dawnorm(insert_default)]  on an Option field, None is inserted as the DEFAULT keyword
+ * #[dawnorm(flatten)]
+ * #[dawnorm(json)]            read and written through dawnorm::Json, for serde types in JSON/JSONB
+ *                              columns, needs the serde feature of dawnorm
+ * #[dawnorm(expr = "sql")]
+ * #[dawnorm(soft_delete)]
+ * #[dawnorm(created_at)]
+ * #[dawnorm(updated_at)]
+ * #[dawnorm(belongs_to = Type, fk = "field", table = "table", references = "field")]
+ * #[dawnorm(has_many = Type, fk = "field", table = "table")]
+ *                              table is required, entities don't know their table as it is
+ *                              given by the dbset! function
+ *
+ * on the struct:
+ * #[dawnorm(read_only)] or #[dawnorm(view)]   only the query side, for views
+ * generic structs get the bounds their fields need on the type parameters, lifetime
+ * parameters are accepted but fields can't borrow them as rows are read into owned values
+ *
+ * on a PgEnum:
+ * #[dawnorm(type_name = "post_state")]        the postgres enum type, snake_case of the enum by default
+ * #[dawnorm(rename_all = "snake_case")]       lowercase, UPPERCASE, PascalCase, camelCase, snake_case,
+ *                                              SCREAMING_SNAKE_CASE or kebab-case, snake_case by default
+ * #[dawnorm(text)]                            stored as TEXT/VARCHAR instead of an enum type
+ * #[dawnorm(int)]                             stored as the discriminant in SMALLINT/INT/BIGINT
+ * on a variant:
+ * #[dawnorm(rename = "value")]
+ */
+
+#[derive(Default, Debug)]
+pub struct StructOptions {
+    pub read_only: bool,
+}
+
+#[derive(Default, Debug, PartialEq)]
+pub enum EnumRepr {
+    #[default]
+    Native,
+    Text,
+    Int,
+}
+
+#[derive(Default, Debug)]
+pub struct EnumOptions {
+    pub type_name: Option<String>,
+    pub rename_all: Option<(String, Span)>,
+    pub repr: EnumRepr,
+}
+
+#[derive(Default, Debug)]
+pub struct FieldOptions {
+    pub rename: Option<String>,
+    pub default: bool,
+    pub insert_default: bool,
+    pub flatten: bool,
+    pub json: bool,
+    pub expr: Option<String>,
+    pub soft_delete: bool,
+    pub created_at: bool,
+    pub updated_at: bool,
+    pub relation: Option<RelationOptions>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RelationKind {
+    BelongsTo,
+    HasMany,
+}
+
+#[derive(Debug, Clone)]
+pub struct RelationOptions {
+    pub kind: RelationKind,
+    pub target: Path,
+    pub fk: Option<String>,
+    pub table: Option<String>,
+    pub references: Option<String>,
+}
+
+enum AttrValue {
+    None,
+    Str(LitStr),
+    Path(Path),
+}
+
+struct AttrArg {
+    name: Ident,
+    value: AttrValue,
+}
+
+impl Parse for AttrArg {
+    fn parse(input: ParseStream) -> syn::Result<Self> {
+        let name: Ident = input.parse()?;
+        if !input.peek(Token![=]) {
+            return Ok(Self { name, value: AttrValue::None });
+        }
+        input.parse::<Token![=]>()?;
+        let value = if input.peek(LitStr) {
+            AttrValue::Str(input.parse()?)
+        } else {
+            AttrValue::Path(input.parse()?)
+        };
+        Ok(Self { name, value })
+    }
+}
+
+impl AttrArg {
+    fn string(&self) -> syn::Result<String> {
+        match &self.value {
+            AttrValue::Str(x) => Ok(x.value()),
+            _ => Err(self.error(format!("{} must be used as {} = \"...\"", self.name, self.name))),
+        }
+    }
+
+    fn path(&self) -> syn::Result<Path> {
+        match &self.value {
+            AttrValue::Path(x) => Ok(x.clone()),
+            _ => Err(self.error(format!("{} must be used as {} = Type", self.name, self.name))),
+        }
+    }
+
+    fn flag(&self) -> syn::Result<bool> {
+        match &self.value {
+            AttrValue::None => Ok(true),
+            _ => Err(self.error(format!("{} does not take a value", self.name))),
+        }
+    }
+
+    fn error(&self, message: String) -> syn::Error {
+        syn::Error::new(self.name.span(), message)
+    }
+}
+
+pub fn get_struct_options(attrs: &[Attribute]) -> syn::Result<StructOptions> {
+    let mut options = StructOptions::default();
+
+    for attr in attrs.iter().filter(|x| x.path.is_ident("dawnorm")) {
+        let args = attr.parse_args_with(Punctuated::<AttrArg, Token![,]>::parse_terminated)?;
+
+        for arg in args {
+            match arg.name.to_string().as_str() {
+                "read_only" | "view" => options.read_only = arg.flag()?,
+                x => return Err(arg.error(format!("unknown dawnorm attribute {}", x))),
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+pub fn get_enum_options(attrs: &[Attribute]) -> syn::Result<EnumOptions> {
+    let mut options = EnumOptions::default();
+
+    for attr in attrs.iter().filter(|x| x.path.is_ident("dawnorm")) {
+        let args = attr.parse_args_with(Punctuated::<AttrArg, Token![,]>::parse_terminated)?;
+
+        for arg in args {
+            match arg.name.to_string().as_str() {
+                "type_name" => options.type_name = Some(arg.string()?),
+                "rename_all" => options.rename_all = Some((arg.string()?, arg.name.span())),
+                "text" if arg.flag()? => options.repr = EnumRepr::Text,
+                "int" if arg.flag()? => options.repr = EnumRepr::Int,
+                x => return Err(arg.error(format!("unknown dawnorm attribute {}", x))),
+            }
+        }
+    }
+
+    Ok(options)
+}
+
+pub fn get_variant_rename(attrs: &[Attribute]) -> syn::Result<Option<String>> {
+    let mut rename = None;
+
+    for attr in attrs.iter().filter(|x| x.path.is_ident("dawnorm")) {
+        let args = attr.parse_args_with(Punctuated::<AttrArg, Token![,]>::parse_terminated)?;
+
+        for arg in args {
+            match arg.name.to_string().as_str() {
+                "rename" => rename = Some(arg.string()?),
+                x => return Err(arg.error(format!("unknown dawnorm attribute {}", x))),
+            }
+        }
+    }
+
+    Ok(rename)
+}
+
+pub fn get_field_options(field: &Field) -> syn::Result<FieldOptions> {
+    let mut options = FieldOptions::default();
+    let mut fk = None;
+    let mut table = None;
+    let mut references = None;
+    let mut relation_arg: Option<Span> = None;
+
+    for attr in field.attrs.iter().filter(|x| x.path.is_ident("dawnorm")) {
+        let args = attr.parse_args_with(Punctuated::<AttrArg, Token![,]>::parse_terminated)?;
+
+        for arg in args {
+            match arg.name.to_string().as_str() {
+                "rename" => options.rename = Some(arg.string()?),
+                "default" => options.default = arg.flag()?,
+                "insert_default" => options.insert_default = arg.flag()?,
+                "flatten" => options.flatten = arg.flag()?,
+                "json" => options.json = arg.flag()?,
+                "expr" => options.expr = Some(arg.string()?),
+                "soft_delete" => options.soft_delete = arg.flag()?,
+                "created_at" => options.created_at = arg.flag()?,
+                "updated_at" => options.updated_at = arg.flag()?,
+                "belongs_to" => options.relation = Some(RelationOptions {
+                    kind: RelationKind::BelongsTo, target: arg.path()?, fk: None, table: None, references: None
+                }),
+                "has_many" => options.relation = Some(RelationOptions {
+                    kind: RelationKind::HasMany, target: arg.path()?, fk: None, table: None, references: None
+                }),
+                "fk" => fk = Some(arg.string()?),
+                "table" => table = Some(arg.string()?),
+                "references" => references = Some(arg.string()?),
+                x => return Err(arg.error(format!("unknown dawnorm attribute {}", x))),
+            }
+            if ["fk", "table", "references"].contains(&arg.name.to_string().as_str()) {
+                relation_arg.get_or_insert(arg.name.span());
+            }
+        }
+    }
+
+    if options.insert_default && !is_option(&field.ty) {
+        return Err(syn::Error::new_spanned(&field.ty, "insert_default can only be used on Option fields"));
+    }
+
+    if options.json && (options.flatten || options.relation.is_some()) {
+        return Err(syn::Error::new_spanned(field, "json can not be combined with flatten or relations"));
+    }
+
+    if let Some(relation) = options.relation.as_mut() {
+        relation.fk = fk;
+        relation.table = table;
+        relation.references = references;
+    } else if let Some(span) = relation_arg {
+        return Err(syn::Error::new(span, "fk, table and references can only be used with belongs_to or has_many"));
+    }
+
+    Ok(options)
+}
+
+fn is_option(ty: &Type) -> bool {
+    match ty {
+        Type::Path(x) => x.qself.is_none() && x.path.segments.last().map(|x| x.ident == "Option").unwrap_or(false),
+        _ => false
+    }
+}
+
+impl FieldOptions {
+    pub fn column(&self, field: &Field) -> String {
+        match &self.rename {
+            Some(x) => x.clone(),
+            None => field.ident.as_ref().unwrap().to_string(),
+        }
+    }
+}
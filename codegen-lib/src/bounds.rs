@@ -0,0 +1,71 @@
+use proc_macro2::{Spacing, TokenStream, TokenTree};
+use quote::ToTokens;
+use syn::{Generics, Type, WherePredicate};
+
+/*
+ * Bounds for generic entities are added per field type, and only for field
+ * types using a type parameter of the struct, e.g. J: FromSql for a field of type J.
+ */
+
+#[derive(Default)]
+struct TypeNames {
+    idents: Vec<String>,
+    lifetimes: Vec<String>,
+}
+
+fn collect_names(tokens: TokenStream, names: &mut TypeNames) {
+    let mut lifetime = false;
+    for token in tokens {
+        match token {
+            TokenTree::Group(x) => collect_names(x.stream(), names),
+            TokenTree::Ident(x) if lifetime => names.lifetimes.push(x.to_string()),
+            TokenTree::Ident(x) => names.idents.push(x.to_string()),
+            TokenTree::Punct(x) => {
+                lifetime = x.as_char() == '\'' && x.spacing() == Spacing::Joint;
+                continue;
+            },
+            TokenTree::Literal(_) => {}
+        }
+        lifetime = false;
+    }
+}
+
+fn type_names(ty: &Type) -> TypeNames {
+    let mut names = TypeNames::default();
+    collect_names(ty.to_token_stream(), &mut names);
+    names
+}
+
+pub fn uses_type_params(ty: &Type, generics: &Generics) -> bool {
+    let names = type_names(ty);
+    generics.type_params().any(|x| names.idents.contains(&x.ident.to_string()))
+}
+
+// the first lifetime parameter of the struct the type borrows
+pub fn borrowed_lifetime(ty: &Type, generics: &Generics) -> Option<String> {
+    let names = type_names(ty);
+    generics.lifetimes()
+        .map(|x| x.lifetime.ident.to_string())
+        .find(|x| names.lifetimes.contains(x))
+}
+
+pub fn with_predicates(generics: &Generics, predicates: Vec<WherePredicate>) -> Generics {
+    let mut generics = generics.clone();
+    generics.make_where_clause().predicates.extend(predicates);
+    generics
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{borrowed_lifetime, uses_type_params};
+
+    #[test]
+    fn bounds_test() {
+        let generics: syn::Generics = syn::parse_quote!(<'a, J>);
+
+        assert!(uses_type_params(&syn::parse_quote!(Option<J>), &generics));
+        assert!(!uses_type_params(&syn::parse_quote!(Option<Json>), &generics));
+        assert_eq!(borrowed_lifetime(&syn::parse_quote!(&'a str), &generics).as_deref(), Some("a"));
+        assert_eq!(borrowed_lifetime(&syn::parse_quote!(&'static str), &generics), None);
+    }
+}
@@ -1,5 +1,12 @@
+mod attrs;
+mod bounds;
+mod pg_enum;
+
+use attrs::{get_field_options, get_struct_options, FieldOptions, RelationKind, RelationOptions};
+use bounds::{borrowed_lifetime, uses_type_params, with_predicates};
+pub use pg_enum::generate_pg_enum_code;
 use proc_macro2::{Span, TokenStream};
-use syn::{Data, DataStruct, DeriveInput, Ident, ImplGenerics, Item, TypeGenerics, WhereClause, Attribute, Path, PathSegment};
+use syn::{Data, DeriveInput, Field, Fields, Generics, Ident, ImplGenerics, Item, TypeGenerics, WhereClause, WherePredicate, Attribute, Path, PathSegment};
 
 /*
  * key_noinsert
@@ -12,174 +19,665 @@ use syn::{Data, DataStruct, DeriveInput, Ident, ImplGenerics, Item, TypeGenerics
 
  #[derive(Default, Debug)]
 struct EntityCodeGenData {
-    key_fields: Vec<String>,
-    insert_fields: Vec<String>,
-    update_fields: Vec<String>,
-    query_fields: Vec<String>
+    key_fields: Vec<EntityField>,
+    insert_fields: Vec<EntityField>,
+    update_fields: Vec<EntityField>,
+    query_fields: Vec<EntityField>,
+    relations: Vec<(Ident, RelationOptions)>,
+    soft_delete: Option<String>,
+    insert_now_fields: Vec<String>,
+    update_now_fields: Vec<String>
 } 
 
-fn get_codegen_data(ds: &DataStruct) -> EntityCodeGenData {
+#[derive(Clone, Debug)]
+struct EntityField {
+    ident: Ident,
+    ty: syn::Type,
+    column: String,
+    insert_default: bool,
+    json: bool
+}
+
+struct MappedField<'a> {
+    field: &'a Field,
+    options: FieldOptions
+}
+
+fn columns(fields: &[EntityField]) -> Vec<String> {
+    fields.iter().map(|x| x.column.clone()).collect()
+}
+
+// json fields are sent through the dawnorm::Json adapter
+fn parm_value(field: &EntityField) -> TokenStream {
+    let ident = &field.ident;
+    if field.json {
+        quote::quote!(dawnorm::Json(self.#ident))
+    } else {
+        quote::quote!(self.#ident)
+    }
+}
+
+fn get_mapped_fields(ast: &DeriveInput) -> syn::Result<Vec<MappedField<'_>>> {
+    let s = match &ast.data {
+        Data::Struct(s) => s,
+        Data::Enum(x) => return Err(syn::Error::new(x.enum_token.span, "enums can not be mapped, only structs with named fields")),
+        Data::Union(x) => return Err(syn::Error::new(x.union_token.span, "unions can not be mapped, only structs with named fields")),
+    };
+
+    match &s.fields {
+        Fields::Named(fields) => fields.named.iter()
+            .map(|field| Ok(MappedField { field, options: get_field_options(field)? }))
+            .collect(),
+        _ => Err(syn::Error::new(ast.ident.span(), "only structs with named fields can be mapped"))
+    }
+}
+
+fn get_codegen_data(name: &Ident, fields: &[MappedField], read_only: bool) -> syn::Result<EntityCodeGenData> {
     let mut entity_data = EntityCodeGenData::default();
 
-    for field in &ds.fields {
-        let field_name = field.ident.as_ref().unwrap().to_string();
-        let attrs = field.attrs.iter().filter_map(|x| 
-            match x {
-                Attribute {path: Path {segments, ..}, ..} => {
-                    match &segments.first() {
-                        Some(PathSegment {ident, .. }) => {
-                            let ident_name = ident.to_string();
-                            Some(ident_name)
-                        },
-                        _ => None
-                    }
-                }
+    for MappedField { field, options } in fields {
+        if options.flatten {
+            return Err(syn::Error::new_spanned(field, "flatten is only supported by FromRow"));
+        }
+
+        if let Some(relation) = &options.relation {
+            entity_data.relations.push((field.ident.clone().unwrap(), relation.clone()));
+            continue;
+        }
+
+        let entity_field = EntityField {
+            ident: field.ident.clone().unwrap(),
+            ty: field.ty.clone(),
+            column: options.column(field),
+            insert_default: options.insert_default,
+            json: options.json
+        };
+        let attrs = field.attrs.iter().filter_map(|Attribute {path: Path {segments, ..}, ..}|
+            match &segments.first() {
+                Some(PathSegment {ident, .. }) => {
+                    let ident_name = ident.to_string();
+                    Some(ident_name)
+                },
+                _ => None
             }
         ).collect::<Vec<String>>();
 
-        entity_data.query_fields.push(field_name.clone());
+        if options.soft_delete {
+            if entity_data.soft_delete.is_some() {
+                return Err(syn::Error::new_spanned(field, "only one field can be marked as soft_delete"));
+            }
+            entity_data.soft_delete = Some(entity_field.column.clone());
+        }
+
+        entity_data.query_fields.push(entity_field.clone());
 
         if attrs.iter().any(|x| x.contains("key")) {
-            entity_data.key_fields.push(field_name.clone());
+            if options.json {
+                return Err(syn::Error::new_spanned(field, "json fields can not be keys"));
+            }
+            entity_data.key_fields.push(entity_field.clone());
+        }
+
+        if options.created_at || options.updated_at {
+            entity_data.insert_now_fields.push(entity_field.column.clone());
+            if options.updated_at {
+                entity_data.update_now_fields.push(entity_field.column.clone());
+            }
+            continue;
         }
 
         if !attrs.iter().any(|x| x.contains("noupdate")) {
-            entity_data.update_fields.push(field_name.clone());
+            entity_data.update_fields.push(entity_field.clone());
         }
 
         if !attrs.iter().any(|x| x.contains("noinsert")) {
-            entity_data.insert_fields.push(field_name.clone());
+            entity_data.insert_fields.push(entity_field.clone());
         }
     }
 
-    entity_data
+    // without a key the generated UPDATE and DELETE statements would be invalid
+    if entity_data.key_fields.is_empty() && !read_only {
+        return Err(syn::Error::new(
+            name.span(),
+            format!("entity {} needs a key field, mark the primary key with #[key] or the struct with #[dawnorm(read_only)]", name)
+        ));
+    }
+
+    Ok(entity_data)
 }
 
 
 pub fn generate_entity_code(ast: &DeriveInput) -> TokenStream {
+    expand_entity(ast).unwrap_or_else(|e| e.to_compile_error())
+}
+
+fn expand_entity(ast: &DeriveInput) -> syn::Result<TokenStream> {
     let name = &ast.ident;
 
-    let (impl_generics, ty_generics, where_clause) = &ast.generics.split_for_impl();
+    let struct_options = get_struct_options(&ast.attrs)?;
+    let fields = get_mapped_fields(ast)?;
+    let codegen_data = get_codegen_data(name, &fields, struct_options.read_only)?;
 
-    let s = match ast.data {
-        Data::Struct(ref s) => s,
-        _ => panic!("Enums or Unions can not be mapped"),
-    };
+    let read_generics = read_generics(&ast.generics, &fields)?;
+    let (impl_generics, ty_generics, where_clause) = &read_generics.split_for_impl();
 
-    let codegen_data = get_codegen_data(&s);
+    let from_row = impl_from_row_trait(&fields, name, impl_generics, ty_generics, where_clause);
+    let entity_trait = impl_entity_trait(name, impl_generics, ty_generics, where_clause, &codegen_data);
+    let entity_fields = impl_entity_fields(&fields, name, &ast.generics);
+    let entity_relations = impl_entity_relations(name, &read_generics, &codegen_data)?;
 
-    let entity_trait = impl_entity_trait(s, name, impl_generics, ty_generics, where_clause, &codegen_data);
-    let entity_fields = impl_entity_fields(s, name, ty_generics, where_clause);
+    // read_only entities only get the query side
+    let write_traits = if struct_options.read_only {
+        TokenStream::new()
+    } else {
+        let write_generics = write_generics(&ast.generics, &read_generics, &codegen_data);
+        let (impl_generics, ty_generics, where_clause) = &write_generics.split_for_impl();
+        let writable_trait = impl_writable_trait(name, impl_generics, ty_generics, where_clause, &codegen_data);
+        let tracking = impl_tracking_trait(name, &write_generics, &codegen_data);
+        quote::quote! {
+            #writable_trait
+
+            #tracking
+        }
+    };
+
+    Ok(quote::quote! {
+        #from_row
 
-    quote::quote! {
         #entity_trait
 
         #entity_fields
+
+        #entity_relations
+
+        #write_traits
+    })
+}
+
+// bounds to read the fields of generic entities, fields borrowing from the struct
+// can not be read as rows are read into owned values
+fn read_generics(generics: &Generics, fields: &[MappedField]) -> syn::Result<Generics> {
+    let mut predicates: Vec<WherePredicate> = Vec::new();
+
+    for MappedField { field, options } in fields {
+        let ty = &field.ty;
+        if options.relation.is_none() && !options.flatten {
+            if let Some(lifetime) = borrowed_lifetime(ty, generics) {
+                return Err(syn::Error::new_spanned(ty, format!(
+                    "field {} borrows '{}, but rows are read into owned values, use an owned type such as String",
+                    field.ident.as_ref().unwrap(), lifetime
+                )));
+            }
+        }
+
+        if !uses_type_params(ty, generics) {
+            continue;
+        }
+        predicates.push(if options.relation.is_some() {
+            syn::parse_quote!(#ty: Default)
+        } else if options.flatten {
+            syn::parse_quote!(#ty: dawnorm::FromRow)
+        } else if options.json && options.default {
+            syn::parse_quote!(dawnorm::Json<#ty>: for<'__r> tokio_postgres::types::FromSql<'__r> + Default)
+        } else if options.json {
+            syn::parse_quote!(dawnorm::Json<#ty>: for<'__r> tokio_postgres::types::FromSql<'__r>)
+        } else if options.default {
+            syn::parse_quote!(#ty: for<'__r> tokio_postgres::types::FromSql<'__r> + Default)
+        } else {
+            syn::parse_quote!(#ty: for<'__r> tokio_postgres::types::FromSql<'__r>)
+        });
+    }
+
+    Ok(with_predicates(generics, predicates))
+}
+
+// bounds to send the fields of generic entities as parameters
+fn write_generics(generics: &Generics, read_generics: &Generics, code_gen_data: &EntityCodeGenData) -> Generics {
+    let mut predicates: Vec<WherePredicate> = Vec::new();
+
+    let written = [&code_gen_data.insert_fields, &code_gen_data.update_fields, &code_gen_data.key_fields];
+    for field in written.into_iter().flatten().filter(|x| uses_type_params(&x.ty, generics)) {
+        let ty = &field.ty;
+        predicates.push(if field.json {
+            syn::parse_quote!(dawnorm::Json<#ty>: tokio_postgres::types::ToSql + Send + Sync + 'static)
+        } else {
+            syn::parse_quote!(#ty: tokio_postgres::types::ToSql + Send + Sync + 'static)
+        });
     }
+    for field in code_gen_data.key_fields.iter().filter(|x| uses_type_params(&x.ty, generics)) {
+        let ty = &field.ty;
+        predicates.push(syn::parse_quote!(#ty: Clone));
+        if code_gen_data.key_fields.len() == 1 {
+            predicates.push(syn::parse_quote!(Vec<#ty>: tokio_postgres::types::ToSql));
+        }
+    }
+
+    with_predicates(read_generics, predicates)
+}
+
+fn fields_struct_path(target: &syn::Path) -> syn::Path {
+    let mut path = target.clone();
+    let last = path.segments.last_mut().unwrap();
+    last.ident = Ident::new(&format!("{}Fields", last.ident), last.ident.span());
+    path
+}
+
+fn impl_entity_relations(
+    name: &Ident,
+    generics: &Generics,
+    code_gen_data: &EntityCodeGenData
+) -> syn::Result<TokenStream> {
+    // loaded relations are sent with the RelationFuture
+    let ty_generics = generics.split_for_impl().1;
+    let generics = if generics.type_params().next().is_some() {
+        with_predicates(generics, vec![syn::parse_quote!(#name #ty_generics: Send)])
+    } else {
+        generics.clone()
+    };
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    // the loader is a nested fn, so it redeclares the generics of the entity
+    let load_params = &generics.params;
+    let load_args = generics.type_params().map(|x| &x.ident).collect::<Vec<&Ident>>();
+    let load_fn = if load_args.is_empty() {
+        quote::quote!(load)
+    } else {
+        quote::quote!(load::<#(#load_args),*>)
+    };
+
+    let relations = code_gen_data.relations.iter().map(|(field, relation)| {
+        let target = &relation.target;
+        let target_fields = fields_struct_path(target);
+        let table = relation.table.clone().ok_or_else(|| syn::Error::new(
+            field.span(),
+            format!("relation {} needs the table of the related entity, e.g. table = \"...\"", field)
+        ))?;
+
+        let load = match relation.kind {
+            RelationKind::HasMany => {
+                let fk = Ident::new(
+                    relation.fk.as_ref().ok_or_else(|| syn::Error::new(field.span(), format!("has_many relation {} needs a fk = \"...\"", field)))?,
+                    Span::mixed_site()
+                );
+                let key = match &relation.references {
+                    Some(x) => Ident::new(x, Span::mixed_site()),
+                    None if code_gen_data.key_fields.len() == 1 => code_gen_data.key_fields[0].ident.clone(),
+                    None => return Err(syn::Error::new(
+                        field.span(),
+                        format!("has_many relation {} needs exactly one key field or references = \"...\"", field)
+                    ))
+                };
+                quote::quote! {
+                    let keys = parents.iter().filter_map(|x| dawnorm::RelationKey::relation_key(&x.#key)).collect::<Vec<_>>();
+                    let query = format!(
+                        "SELECT {} FROM {} WHERE {} = ANY($1) AND {};",
+                        <#target as dawnorm::Entity>::sql_fields(), #table, #target_fields::#fk(),
+                        dawnorm::not_deleted::<#target>(#table).unwrap_or_else(|| "TRUE".into())
+                    );
+                    let children: Vec<#target> = client.query_as(&query, dawnorm::parms![keys]).await?;
+                    for child in children {
+                        let key = dawnorm::RelationKey::relation_key(&child.#fk);
+                        if let Some(parent) = parents.iter_mut().find(|x| key.is_some() && dawnorm::RelationKey::relation_key(&x.#key) == key) {
+                            parent.#field.push(child);
+                        }
+                    }
+                }
+            },
+            RelationKind::BelongsTo => {
+                let fk = Ident::new(
+                    &relation.fk.clone().unwrap_or_else(|| format!("{}_id", field)),
+                    Span::mixed_site()
+                );
+                let references = Ident::new(relation.references.as_deref().unwrap_or("id"), Span::mixed_site());
+                quote::quote! {
+                    let keys = parents.iter().filter_map(|x| dawnorm::RelationKey::relation_key(&x.#fk)).collect::<Vec<_>>();
+                    let query = format!(
+                        "SELECT {} FROM {} WHERE {} = ANY($1) AND {};",
+                        <#target as dawnorm::Entity>::sql_fields(), #table, #target_fields::#references(),
+                        dawnorm::not_deleted::<#target>(#table).unwrap_or_else(|| "TRUE".into())
+                    );
+                    let related: Vec<#target> = client.query_as(&query, dawnorm::parms![keys]).await?;
+                    for parent in parents.iter_mut() {
+                        let key = dawnorm::RelationKey::relation_key(&parent.#fk);
+                        parent.#field = related.iter().find(|x| key.is_some() && dawnorm::RelationKey::relation_key(&x.#references) == key).cloned();
+                    }
+                }
+            }
+        };
+
+        Ok(quote::quote! {
+            pub fn #field() -> dawnorm::Relation<Self> {
+                fn load<'__a, #load_params>(
+                    client: dawnorm::Connection<'__a>,
+                    parents: &'__a mut [#name #ty_generics]
+                ) -> dawnorm::RelationFuture<'__a> #where_clause {
+                    Box::pin(async move {
+                        #load
+                        Ok(())
+                    })
+                }
+                dawnorm::Relation::new(#load_fn)
+            }
+        })
+    }).collect::<syn::Result<Vec<TokenStream>>>()?;
+
+    if relations.is_empty() {
+        return Ok(TokenStream::new());
+    }
+
+    Ok(quote::quote! {
+        impl #impl_generics #name #ty_generics #where_clause {
+            #(#relations)*
+        }
+    })
 }
 
 fn impl_entity_fields(
-    s: &DataStruct,
+    fields: &[MappedField],
     name: &Ident,
-    ty_generics: &TypeGenerics,
-    where_clause: &Option<&WhereClause>,
+    generics: &Generics,
 ) -> TokenStream { 
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
     let new_name = Ident::new(&format!("{}Fields", name), Span::mixed_site());
 
-    let fields = s.fields.iter().map(|field| {
+    let fields = fields.iter().filter(|x| x.options.relation.is_none()).map(|MappedField { field, options }| {
         let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
 
-        let row_expr = format!(r##"{}"##, ident);
+        let ty = if options.json {
+            quote::quote!(dawnorm::Json<#ty>)
+        } else {
+            quote::quote!(#ty)
+        };
+
+        let row_expr = options.column(field);
         quote::quote! {
-            pub fn #ident() -> &'static str { #row_expr }
+            pub fn #ident() -> dawnorm::Field<#ty> { dawnorm::Field::new(#row_expr) }
         }
     });
 
     let tokens = quote::quote! {
-        pub struct #new_name #ty_generics #where_clause { }
-        impl #new_name {
+        pub struct #new_name #impl_generics #where_clause {
+            phantom: std::marker::PhantomData<fn() -> #name #ty_generics>
+        }
+        impl #impl_generics #new_name #ty_generics #where_clause {
             #(#fields)*
         }
     };
     tokens
 }
 
+fn generate_from_row_fields(fields: &[MappedField]) -> Vec<TokenStream> {
+    fields.iter().map(|MappedField { field, options }| {
+        let ident = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let row_expr = options.column(field);
+        if options.relation.is_some() {
+            quote::quote! {
+                #ident:Default::default()
+            }
+        } else if options.flatten {
+            quote::quote! {
+                #ident:<#ty as dawnorm::FromRow>::from_row_prefixed(row, prefix)?
+            }
+        } else if options.json && options.default {
+            quote::quote! {
+                #ident:dawnorm::get_or_default::<dawnorm::Json<#ty>>(row, &dawnorm::prefixed(prefix, #row_expr))?.0
+            }
+        } else if options.json {
+            quote::quote! {
+                #ident:row.try_get::<&str,dawnorm::Json<#ty>>(&dawnorm::prefixed(prefix, #row_expr))?.0
+            }
+        } else if options.default {
+            quote::quote! {
+                #ident:dawnorm::get_or_default::<#ty>(row, &dawnorm::prefixed(prefix, #row_expr))?
+            }
+        } else {
+            quote::quote! {
+                #ident:row.try_get::<&str,#ty>(&dawnorm::prefixed(prefix, #row_expr))?
+            }
+        }
+    }).collect()
+}
+
+fn impl_from_row_trait(
+    fields: &[MappedField],
+    name: &Ident,
+    impl_generics: &ImplGenerics,
+    ty_generics: &TypeGenerics,
+    where_clause: &Option<&WhereClause>,
+) -> TokenStream {
+    let fields = generate_from_row_fields(fields);
+
+    quote::quote! {
+        impl #impl_generics dawnorm::FromRow for #name #ty_generics #where_clause {
+            fn from_row_prefixed(row: &tokio_postgres::row::Row, prefix: &str) -> ::std::result::Result<Self, dawnorm::Error> {
+                Ok(Self {
+                    #(#fields),*
+                })
+            }
+        }
+    }
+}
+
+pub fn generate_from_row_code(ast: &DeriveInput) -> TokenStream {
+    expand_from_row(ast).unwrap_or_else(|e| e.to_compile_error())
+}
+
+fn expand_from_row(ast: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = &ast.ident;
+
+    let fields = get_mapped_fields(ast)?;
+
+    let read_generics = read_generics(&ast.generics, &fields)?;
+    let (impl_generics, ty_generics, where_clause) = &read_generics.split_for_impl();
+
+    Ok(impl_from_row_trait(&fields, name, impl_generics, ty_generics, where_clause))
+}
+
+pub fn generate_projection_code(ast: &DeriveInput) -> TokenStream {
+    expand_projection(ast).unwrap_or_else(|e| e.to_compile_error())
+}
+
+fn expand_projection(ast: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = &ast.ident;
+
+    let fields = get_mapped_fields(ast)?;
+
+    let read_generics = read_generics(&ast.generics, &fields)?;
+    let (impl_generics, ty_generics, where_clause) = &read_generics.split_for_impl();
+
+    let from_row = impl_from_row_trait(&fields, name, impl_generics, ty_generics, where_clause);
+
+    let sql_fiels = fields.iter().map(|MappedField { field, options }| {
+        if options.flatten || options.relation.is_some() {
+            return Err(syn::Error::new_spanned(field, "flatten and relations are not supported by Projection"));
+        }
+        let column = options.column(field);
+        Ok(match &options.expr {
+            Some(expr) => format!("{} AS {}", expr, column),
+            None => column
+        })
+    }).collect::<syn::Result<Vec<String>>>()?.join(", ");
+
+    Ok(quote::quote! {
+        #from_row
+
+        impl #impl_generics dawnorm::Projection for #name #ty_generics #where_clause {
+            fn sql_fields() -> &'static str {
+                #sql_fiels
+            }
+        }
+    })
+}
+
 fn generate_args_list(len: usize, offset: usize) -> String {
     (offset..len+offset).map(|x| format!("${}", x + 1))
         .collect::<Vec<String>>().join(", ")
 }
 
+fn generate_values_list(parm_len: usize, now_len: usize) -> String {
+    (0..parm_len).map(|x| format!("${}", x + 1))
+        .chain((0..now_len).map(|_| "now()".to_string()))
+        .collect::<Vec<String>>().join(", ")
+}
+
 fn generate_key_constraint(keys: &[String], parm_offset: usize) -> String {
     format!("({}) = ({})", keys.join(", "), generate_args_list(keys.len(), parm_offset))
 }
 
 fn impl_entity_trait(
-    s: &DataStruct,
     name: &Ident,
     impl_generics: &ImplGenerics,
     ty_generics: &TypeGenerics,
     where_clause: &Option<&WhereClause>,
     code_gen_data: &EntityCodeGenData
 ) -> Item {
-    let fields = s.fields.iter().map(|field| {
-        let ident = field.ident.as_ref().unwrap();
-        let ty = &field.ty;
-        let row_expr = format!(r##"{}"##, ident);
-        quote::quote! {
-            #ident:row.try_get::<&str,#ty>(#row_expr)?
+    let soft_delete = match &code_gen_data.soft_delete {
+        Some(x) => quote::quote!(Some(#x)),
+        None => quote::quote!(None)
+    };
+
+    let sql_fiels = columns(&code_gen_data.query_fields).join(", ");
+    let sql_field_names = columns(&code_gen_data.query_fields);
+    let sql_table_fiels = code_gen_data.query_fields.iter().map(|x| format!("{{table_name}}.{0} AS \"{{table_name}}.{0}\"", x.column)).collect::<Vec<String>>().join(", ");
+
+    let tokens = quote::quote! {
+        impl #impl_generics dawnorm::Entity for #name #ty_generics #where_clause {
+            fn sql_fields() -> &'static str {
+                #sql_fiels
+            }
+
+            fn sql_field_names() -> &'static [&'static str] {
+                &[#(#sql_field_names),*]
+            }
+
+            fn sql_soft_delete_field() -> Option<&'static str> {
+                #soft_delete
+            }
+
+            fn sql_table_fields(table_name: &str) -> String {
+                format!(#sql_table_fiels)
+            }
         }
-    });
+    };
+
+    syn::parse_quote!(#tokens)
+}
 
+fn impl_writable_trait(
+    name: &Ident,
+    impl_generics: &ImplGenerics,
+    ty_generics: &TypeGenerics,
+    where_clause: &Option<&WhereClause>,
+    code_gen_data: &EntityCodeGenData
+) -> Item {
     let insert_query = format!(
         "INSERT INTO {{}} ({}) VALUES ({}) RETURNING {};",
-        code_gen_data.insert_fields.join(", "),
-        generate_args_list(code_gen_data.insert_fields.len(), 0),
-        code_gen_data.query_fields.join(", ")
+        [columns(&code_gen_data.insert_fields), code_gen_data.insert_now_fields.clone()].concat().join(", "),
+        generate_values_list(code_gen_data.insert_fields.len(), code_gen_data.insert_now_fields.len()),
+        columns(&code_gen_data.query_fields).join(", ")
     );
-    let insert_parms = code_gen_data.insert_fields.iter()
-        .map(|x| Ident::new(x, Span::mixed_site()))
-        .map(|x| quote::quote!(self.#x));
+    let insert_parms = code_gen_data.insert_fields.iter().map(parm_value);
+
+    // the tables of the relations order the changes of ChangeTracker
+    let relation_tables = |kind: RelationKind| code_gen_data.relations.iter()
+        .filter(move |(_, x)| x.kind == kind)
+        .filter_map(|(_, x)| x.table.clone());
+    let parent_tables = relation_tables(RelationKind::BelongsTo);
+    let child_tables = relation_tables(RelationKind::HasMany);
+
+    let insert_body = if code_gen_data.insert_fields.iter().any(|x| x.insert_default) {
+        // insert_default fields holding None are sent as the DEFAULT keyword,
+        // so the values list is only known at runtime
+        let insert_values = code_gen_data.insert_fields.iter().map(|x| {
+            let ident = &x.ident;
+            let value = parm_value(x);
+            if x.insert_default {
+                quote::quote! {
+                    if self.#ident.is_none() {
+                        values.push("DEFAULT".to_string());
+                    } else {
+                        parms.push(Box::new(#value));
+                        values.push(format!("${}", parms.len()));
+                    }
+                }
+            } else {
+                quote::quote! {
+                    parms.push(Box::new(#value));
+                    values.push(format!("${}", parms.len()));
+                }
+            }
+        });
+        let insert_now_values = code_gen_data.insert_now_fields.iter().map(|_| quote::quote!(values.push("now()".to_string());));
+        let insert_query = format!(
+            "INSERT INTO {{}} ({}) VALUES ({{}}) RETURNING {};",
+            [columns(&code_gen_data.insert_fields), code_gen_data.insert_now_fields.clone()].concat().join(", "),
+            columns(&code_gen_data.query_fields).join(", ")
+        );
+        quote::quote! {
+            let mut parms: Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>> = Vec::new();
+            let mut values: Vec<String> = Vec::new();
+            #(#insert_values)*
+            #(#insert_now_values)*
+            (format!(#insert_query, table_name, values.join(", ")), parms)
+        }
+    } else {
+        quote::quote! {
+            (format!(#insert_query, table_name), dawnorm::parms![#(#insert_parms),*])
+        }
+    };
 
     let update_query = format!(
-        "UPDATE {{}} SET ({}) = ({}) WHERE {} RETURNING {};",
-        code_gen_data.update_fields.join(", "),
-        generate_args_list(code_gen_data.update_fields.len(), 0),
-        generate_key_constraint(&code_gen_data.key_fields, code_gen_data.update_fields.len()),
-        code_gen_data.query_fields.join(", ")
+        "UPDATE {{}} SET ({}) = ROW({}) WHERE {} RETURNING {};",
+        [columns(&code_gen_data.update_fields), code_gen_data.update_now_fields.clone()].concat().join(", "),
+        generate_values_list(code_gen_data.update_fields.len(), code_gen_data.update_now_fields.len()),
+        generate_key_constraint(&columns(&code_gen_data.key_fields), code_gen_data.update_fields.len()),
+        columns(&code_gen_data.query_fields).join(", ")
     );
 
-    let update_parms = 
-        [code_gen_data.update_fields.clone(), code_gen_data.key_fields.clone()].concat().into_iter()
-        .map(|x| Ident::new(&x, Span::mixed_site()))
-        .map(|x| quote::quote!(self.#x));
+    let update_parms = code_gen_data.update_fields.iter()
+        .chain(code_gen_data.key_fields.iter())
+        .map(parm_value);
 
-    let delete_query = format!(
-        "DELETE FROM {{}} WHERE {}",
-        generate_key_constraint(&code_gen_data.key_fields, 0)
-    );
+    let delete_query = match &code_gen_data.soft_delete {
+        Some(soft_delete) => format!(
+            "UPDATE {{}} SET {0} = now() WHERE {1} AND {0} IS NULL",
+            soft_delete,
+            generate_key_constraint(&columns(&code_gen_data.key_fields), 0)
+        ),
+        None => format!(
+            "DELETE FROM {{}} WHERE {}",
+            generate_key_constraint(&columns(&code_gen_data.key_fields), 0)
+        )
+    };
 
     let delete_parms = code_gen_data.key_fields.iter()
-    .map(|x| Ident::new(x, Span::mixed_site()))
-    .map(|x| quote::quote!(self.#x));
+    .map(|x| &x.ident)
+    .map(|x| quote::quote!(::std::clone::Clone::clone(&self.#x)));
 
-    let sql_fiels = code_gen_data.query_fields.join(", ");
-    let sql_table_fiels = code_gen_data.query_fields.iter().map(|x| format!("{{table_name}}.{}", x)).collect::<Vec<String>>().join(", ");
-    let key_constrint = generate_key_constraint(&code_gen_data.key_fields, 0);
+    let key_constrint = generate_key_constraint(&columns(&code_gen_data.key_fields), 0);
+
+    let (key_type, key_parms, key_filter) = generate_key_code(&code_gen_data.key_fields);
 
     let tokens = quote::quote! {
-        impl #impl_generics dawnorm::Entity for #name #ty_generics #where_clause {
-            fn from_row(row: tokio_postgres::row::Row) -> ::std::result::Result<Self, dawnorm::Error> {
-                Ok(Self {
-                    #(#fields),*
-                })
+        impl #impl_generics dawnorm::Writable for #name #ty_generics #where_clause {
+            type Key = #key_type;
+
+            fn sql_parent_tables() -> &'static [&'static str] {
+                &[#(#parent_tables),*]
             }
 
-            fn sql_fields() -> &'static str {
-                #sql_fiels
+            fn sql_child_tables() -> &'static [&'static str] {
+                &[#(#child_tables),*]
             }
 
-            fn sql_table_fields(table_name: &str) -> String {
-                format!(#sql_table_fiels)
+            fn key_parms(key: Self::Key) -> Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>> {
+                #key_parms
+            }
+
+            fn sql_key_filter(keys: Vec<Self::Key>) -> (String, Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>) {
+                #key_filter
             }
 
             fn sql_key_constrint() -> &'static str {
@@ -187,7 +685,7 @@ fn impl_entity_trait(
             }
 
             fn get_insert_query(self, table_name: &str) -> (String, Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>) {
-                (format!(#insert_query, table_name), dawnorm::parms![#(#insert_parms),*])
+                #insert_body
             }
 
             fn get_update_query(self, table_name: &str) -> (String, Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>) {
@@ -203,9 +701,114 @@ fn impl_entity_trait(
     syn::parse_quote!(#tokens)
 }
 
+// a scalar key uses = ANY($1), composite keys a row value IN list
+fn generate_key_code(key_fields: &[EntityField]) -> (TokenStream, TokenStream, TokenStream) {
+    if let [key] = key_fields {
+        let ty = &key.ty;
+        let key_filter = format!("{} = ANY($1)", key.column);
+        return (
+            quote::quote!(#ty),
+            quote::quote!(dawnorm::parms![key]),
+            quote::quote!((#key_filter.to_string(), dawnorm::parms![keys]))
+        );
+    }
+
+    let types = key_fields.iter().map(|x| &x.ty);
+    let idents = key_fields.iter().map(|x| &x.ident).collect::<Vec<&Ident>>();
+    let key_filter = format!("({}) IN ({{}})", columns(key_fields).join(", "));
+    (
+        quote::quote!((#(#types),*)),
+        quote::quote! {
+            let (#(#idents),*) = key;
+            dawnorm::parms![#(#idents),*]
+        },
+        quote::quote! {
+            let mut parms: Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>> = Vec::new();
+            let mut rows: Vec<String> = Vec::new();
+            for (#(#idents),*) in keys {
+                let mut placeholders: Vec<String> = Vec::new();
+                #(
+                    parms.push(Box::new(#idents));
+                    placeholders.push(format!("${}", parms.len()));
+                )*
+                rows.push(format!("({})", placeholders.join(", ")));
+            }
+            (format!(#key_filter, rows.join(", ")), parms)
+        }
+    )
+}
+
+fn impl_tracking_trait(name: &Ident, generics: &syn::Generics, code_gen_data: &EntityCodeGenData) -> Item {
+    let key_columns = columns(&code_gen_data.key_fields);
+    let tracked_fields = code_gen_data.update_fields.iter()
+        .filter(|x| !key_columns.contains(&x.column))
+        .collect::<Vec<&EntityField>>();
+
+    // the higher ranked bound keeps entities with fields that are not PartialEq compiling,
+    // they just don't implement Tracking
+    let mut generics = generics.clone();
+    let where_clause = generics.make_where_clause();
+    for field in &tracked_fields {
+        let ty = &field.ty;
+        where_clause.predicates.push(syn::parse_quote!(for<'__dawnorm> #ty: PartialEq));
+    }
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let changed = tracked_fields.iter()
+        .map(|x| {
+            let ident = &x.ident;
+            quote::quote!(self.#ident != original.#ident)
+        })
+        .reduce(|a, b| quote::quote!(#a || #b))
+        .unwrap_or_else(|| quote::quote!(false));
+    let sets = tracked_fields.iter().map(|x| {
+        let ident = &x.ident;
+        let column = &x.column;
+        let value = parm_value(x);
+        quote::quote! {
+            if self.#ident != original.#ident {
+                parms.push(Box::new(#value));
+                sets.push(format!("{} = ${}", #column, parms.len()));
+            }
+        }
+    });
+    let now_sets = code_gen_data.update_now_fields.iter().map(|x| format!("{} = now()", x));
+    let keys = code_gen_data.key_fields.iter().map(|x| {
+        let ident = &x.ident;
+        let column = &x.column;
+        quote::quote! {
+            parms.push(Box::new(self.#ident));
+            keys.push(format!("{} = ${}", #column, parms.len()));
+        }
+    });
+    let update_query = format!(
+        "UPDATE {{}} SET {{}} WHERE {{}} RETURNING {};",
+        columns(&code_gen_data.query_fields).join(", ")
+    );
+
+    let tokens = quote::quote! {
+        impl #impl_generics dawnorm::Tracking for #name #ty_generics #where_clause {
+            fn get_tracked_update_query(self, original: &Self, table_name: &str) -> Result<(String, Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>), Self> {
+                if !(#changed) {
+                    return Err(self);
+                }
+                let mut parms: Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>> = Vec::new();
+                let mut sets: Vec<String> = Vec::new();
+                let mut keys: Vec<String> = Vec::new();
+                #(#sets)*
+                #(sets.push(#now_sets.to_string());)*
+                #(#keys)*
+                Ok((format!(#update_query, table_name, sets.join(", "), keys.join(" AND ")), parms))
+            }
+        }
+    };
+
+    syn::parse_quote!(#tokens)
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::generate_entity_code;
+    use crate::{generate_entity_code, generate_from_row_code, generate_projection_code};
 
     #[test]
     pub fn test() {
@@ -227,4 +830,365 @@ mod tests {
 
         std::fs::write("/tmp/test.rs", format!("{}", out)).unwrap();
     }
+
+    #[test]
+    pub fn projection_test() {
+        let ts = quote::quote!(
+            pub struct PostSummary {
+                id: i32,
+                #[dawnorm(expr = "length(title)")]
+                title_length: i32
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_projection_code(&ast).to_string();
+
+        assert!(out.contains(r#""id, length(title) AS title_length""#));
+    }
+
+    #[test]
+    pub fn from_row_test() {
+        let ts = quote::quote!(
+            pub struct AuthorReport {
+                #[dawnorm(rename = "author_name")]
+                name: String,
+                #[dawnorm(default)]
+                post_count: i64,
+                #[dawnorm(flatten)]
+                stats: Stats
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_from_row_code(&ast).to_string();
+
+        assert!(out.contains(r#"try_get :: < & str , String > (& dawnorm :: prefixed (prefix , "author_name"))"#));
+        assert!(out.contains(r#"dawnorm :: get_or_default :: < i64 > (row , & dawnorm :: prefixed (prefix , "post_count"))"#));
+        assert!(out.contains("< Stats as dawnorm :: FromRow > :: from_row_prefixed (row , prefix)"));
+    }
+
+    #[test]
+    pub fn relation_test() {
+        let ts = quote::quote!(
+            pub struct Post {
+                #[key_noinsert_noupdate]
+                id: i32,
+                author_id: i32,
+                #[dawnorm(belongs_to = Author, table = "authors")]
+                author: Option<Author>,
+                #[dawnorm(has_many = Comment, fk = "post_id", table = "comments")]
+                comments: Vec<Comment>
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains(r#""id, author_id""#));
+        assert!(out.contains("pub fn author () -> dawnorm :: Relation < Self >"));
+        assert!(out.contains("AuthorFields :: id ()"));
+        assert!(out.contains("pub fn comments () -> dawnorm :: Relation < Self >"));
+        assert!(out.contains("CommentFields :: post_id ()"));
+        assert!(out.contains(r#"fn sql_parent_tables () -> & 'static [& 'static str] { & ["authors"] }"#));
+        assert!(out.contains(r#"fn sql_child_tables () -> & 'static [& 'static str] { & ["comments"] }"#));
+    }
+
+    #[test]
+    pub fn soft_delete_test() {
+        let ts = quote::quote!(
+            pub struct Comment {
+                #[key_noinsert_noupdate]
+                id: i32,
+                #[dawnorm(soft_delete)]
+                deleted_at: Option<std::time::SystemTime>
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains(r#""UPDATE {} SET deleted_at = now() WHERE (id) = ($1) AND deleted_at IS NULL""#));
+        assert!(out.contains(r#"Some ("deleted_at")"#));
+    }
+
+    #[test]
+    pub fn timestamps_test() {
+        let ts = quote::quote!(
+            pub struct Post {
+                #[key_noinsert_noupdate]
+                id: i32,
+                title: String,
+                #[dawnorm(created_at)]
+                created_at: std::time::SystemTime,
+                #[dawnorm(updated_at)]
+                updated_at: std::time::SystemTime
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains(r#""INSERT INTO {} (title, created_at, updated_at) VALUES ($1, now(), now()) RETURNING id, title, created_at, updated_at;""#));
+        assert!(out.contains(r#""UPDATE {} SET (title, updated_at) = ROW($1, now()) WHERE (id) = ($2) RETURNING id, title, created_at, updated_at;""#));
+    }
+
+    #[test]
+    pub fn insert_default_test() {
+        let ts = quote::quote!(
+            pub struct Post {
+                #[key_noinsert_noupdate]
+                id: i32,
+                title: String,
+                #[dawnorm(insert_default)]
+                views: Option<i32>
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains(r#""INSERT INTO {} (title, views) VALUES ({}) RETURNING id, title, views;""#));
+        assert!(out.contains(r#"if self . views . is_none () { values . push ("DEFAULT" . to_string ()) ; }"#));
+
+        let ts = quote::quote!(
+            pub struct Post {
+                #[key_noinsert_noupdate]
+                id: i32,
+                #[dawnorm(default)]
+                active: bool
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(!out.contains("DEFAULT"));
+
+        let ts = quote::quote!(
+            pub struct Post {
+                #[key_noinsert_noupdate]
+                id: i32,
+                #[dawnorm(insert_default)]
+                active: bool
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("insert_default can only be used on Option fields"));
+    }
+
+    #[test]
+    pub fn key_test() {
+        let ts = quote::quote!(
+            pub struct PostTag {
+                #[key]
+                post_id: i32,
+                #[key]
+                tag: String
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("type Key = (i32 , String) ;"));
+        assert!(out.contains(r#"format ! ("(post_id, tag) IN ({})" , rows . join (", "))"#));
+
+        let ts = quote::quote!(
+            pub struct Post {
+                #[key_noinsert_noupdate]
+                id: i32,
+                title: String
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("type Key = i32 ;"));
+        assert!(out.contains(r#"("id = ANY($1)" . to_string () , dawnorm :: parms ! [keys])"#));
+    }
+
+    #[test]
+    pub fn compile_error_test() {
+        let ts = quote::quote!(
+            pub struct Log {
+                message: String
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.starts_with("compile_error !"));
+        assert!(out.contains("entity Log needs a key field"));
+
+        let ts = quote::quote!(
+            pub enum State {
+                Draft
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_projection_code(&ast).to_string();
+
+        assert!(out.contains("enums can not be mapped"));
+
+        let ts = quote::quote!(
+            pub struct Post {
+                #[key]
+                id: i32,
+                #[dawnorm(colour)]
+                title: String
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("unknown dawnorm attribute colour"));
+
+        let ts = quote::quote!(
+            pub struct Post {
+                #[key]
+                id: i32,
+                author_id: i32,
+                #[dawnorm(belongs_to = Author)]
+                author: Option<Author>
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("relation author needs the table of the related entity"));
+    }
+
+    #[test]
+    pub fn read_only_test() {
+        let ts = quote::quote!(
+            #[dawnorm(view)]
+            pub struct PostStats {
+                author_id: i32,
+                posts: i64
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("impl dawnorm :: Entity for PostStats"));
+        assert!(!out.contains("dawnorm :: Writable"));
+        assert!(!out.contains("dawnorm :: Tracking"));
+    }
+
+    #[test]
+    pub fn generic_test() {
+        let ts = quote::quote!(
+            pub struct Setting<J> {
+                #[key]
+                name: String,
+                value: J
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("impl < J > dawnorm :: FromRow for Setting < J > where J : for < '__r > tokio_postgres :: types :: FromSql < '__r >"));
+        assert!(out.contains("J : tokio_postgres :: types :: ToSql + Send + Sync + 'static"));
+        assert!(out.contains("pub struct SettingFields < J > { phantom : std :: marker :: PhantomData < fn () -> Setting < J > > }"));
+        assert!(out.contains("impl < J > SettingFields < J >"));
+
+        let ts = quote::quote!(
+            pub struct Setting<'a> {
+                #[key]
+                name: &'a str
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("field name borrows 'a"));
+    }
+
+    #[test]
+    pub fn tracking_test() {
+        let ts = quote::quote!(
+            pub struct Post {
+                #[key_noinsert_noupdate]
+                id: i32,
+                title: String,
+                #[noupdate]
+                author_id: i32,
+                #[dawnorm(updated_at)]
+                updated_at: std::time::SystemTime
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("impl dawnorm :: Tracking for Post where for < '__dawnorm > String : PartialEq"));
+        assert!(out.contains(r#"if ! (self . title != original . title) { return Err (self) ; }"#));
+        assert!(out.contains(r#"sets . push ("updated_at = now()" . to_string ()) ;"#));
+        assert!(!out.contains("original . author_id"));
+    }
+
+    #[test]
+    pub fn json_test() {
+        let ts = quote::quote!(
+            pub struct Author {
+                #[key_noinsert_noupdate]
+                id: i32,
+                #[dawnorm(json)]
+                settings: Settings
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("settings : row . try_get :: < & str , dawnorm :: Json < Settings >> (& dawnorm :: prefixed (prefix , \"settings\")) ? . 0"));
+        assert!(out.contains("dawnorm :: parms ! [dawnorm :: Json (self . settings) , self . id]"));
+        assert!(out.contains("parms . push (Box :: new (dawnorm :: Json (self . settings))) ;"));
+        assert!(out.contains("pub fn settings () -> dawnorm :: Field < dawnorm :: Json < Settings > >"));
+
+        let ts = quote::quote!(
+            pub struct Author {
+                #[key]
+                #[dawnorm(json)]
+                id: Settings
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_entity_code(&ast).to_string();
+
+        assert!(out.contains("json fields can not be keys"));
+    }
 }
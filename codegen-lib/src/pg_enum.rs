@@ -0,0 +1,210 @@
+use proc_macro2::{Span, TokenStream};
+use syn::{Data, DeriveInput, Fields, Ident};
+
+use crate::attrs::{get_enum_options, get_variant_rename, EnumRepr};
+
+fn words(name: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
+    for c in name.chars() {
+        match words.last_mut() {
+            Some(word) if !c.is_uppercase() && c != '_' => word.push(c),
+            _ if c == '_' => words.push(String::new()),
+            _ => words.push(c.to_string()),
+        }
+    }
+    words.into_iter().filter(|x| !x.is_empty()).map(|x| x.to_lowercase()).collect()
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(x) => x.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+fn rename(name: &str, rule: &str, span: Span) -> syn::Result<String> {
+    let words = words(name);
+    Ok(match rule {
+        "lowercase" => words.concat(),
+        "UPPERCASE" => words.concat().to_uppercase(),
+        "PascalCase" => words.iter().map(|x| capitalize(x)).collect(),
+        "camelCase" => words.iter().enumerate().map(|(i, x)| if i == 0 { x.clone() } else { capitalize(x) }).collect(),
+        "snake_case" => words.join("_"),
+        "SCREAMING_SNAKE_CASE" => words.join("_").to_uppercase(),
+        "kebab-case" => words.join("-"),
+        x => return Err(syn::Error::new(span, format!("unknown rename_all rule {}", x))),
+    })
+}
+
+pub fn generate_pg_enum_code(ast: &DeriveInput) -> TokenStream {
+    expand_pg_enum(ast).unwrap_or_else(|e| e.to_compile_error())
+}
+
+fn expand_pg_enum(ast: &DeriveInput) -> syn::Result<TokenStream> {
+    let name = &ast.ident;
+
+    let e = match &ast.data {
+        Data::Enum(e) => e,
+        _ => return Err(syn::Error::new(name.span(), "PgEnum can only be derived for enums")),
+    };
+    if !ast.generics.params.is_empty() {
+        return Err(syn::Error::new_spanned(&ast.generics, "PgEnum can not be derived for generic enums"));
+    }
+
+    let options = get_enum_options(&ast.attrs)?;
+    let (rule, rule_span) = options.rename_all.clone().unwrap_or_else(|| ("snake_case".into(), Span::call_site()));
+
+    let mut variants: Vec<(&Ident, String)> = Vec::new();
+    for variant in &e.variants {
+        if !matches!(variant.fields, Fields::Unit) {
+            return Err(syn::Error::new_spanned(&variant.fields, "PgEnum variants can not have fields"));
+        }
+        let value = match get_variant_rename(&variant.attrs)? {
+            Some(x) => x,
+            None => rename(&variant.ident.to_string(), &rule, rule_span)?,
+        };
+        variants.push((&variant.ident, value));
+    }
+
+    let idents = variants.iter().map(|x| x.0).collect::<Vec<&Ident>>();
+    let values = variants.iter().map(|x| &x.1).collect::<Vec<&String>>();
+    let unknown = format!("unknown {} value {{}}", name);
+    let out_of_range = format!("{} value {{}} is out of range for {{}}", name);
+
+    let (to_sql, from_sql, accepts) = match options.repr {
+        EnumRepr::Native | EnumRepr::Text => {
+            let accepts = if options.repr == EnumRepr::Native {
+                let type_name = options.type_name.clone().unwrap_or_else(|| rename(&name.to_string(), "snake_case", Span::call_site()).unwrap());
+                quote::quote!(ty.name() == #type_name)
+            } else {
+                quote::quote!(<&str as tokio_postgres::types::ToSql>::accepts(ty))
+            };
+            (
+                quote::quote! {
+                    let value = match self {
+                        #(#name::#idents => #values,)*
+                    };
+                    <&str as tokio_postgres::types::ToSql>::to_sql(&value, ty, out)
+                },
+                quote::quote! {
+                    match <&str as tokio_postgres::types::FromSql>::from_sql(ty, raw)? {
+                        #(#values => Ok(#name::#idents),)*
+                        x => Err(format!(#unknown, x).into())
+                    }
+                },
+                accepts
+            )
+        },
+        EnumRepr::Int => (
+            quote::quote! {
+                let value = match self {
+                    #(#name::#idents => #name::#idents as i64,)*
+                };
+                let out_of_range = |_| format!(#out_of_range, value, ty);
+                match *ty {
+                    tokio_postgres::types::Type::INT2 => i16::try_from(value).map_err(out_of_range)?.to_sql(ty, out),
+                    tokio_postgres::types::Type::INT4 => i32::try_from(value).map_err(out_of_range)?.to_sql(ty, out),
+                    _ => value.to_sql(ty, out),
+                }
+            },
+            quote::quote! {
+                let value = match *ty {
+                    tokio_postgres::types::Type::INT2 => <i16 as tokio_postgres::types::FromSql>::from_sql(ty, raw)? as i64,
+                    tokio_postgres::types::Type::INT4 => <i32 as tokio_postgres::types::FromSql>::from_sql(ty, raw)? as i64,
+                    _ => <i64 as tokio_postgres::types::FromSql>::from_sql(ty, raw)?,
+                };
+                match value {
+                    #(x if x == #name::#idents as i64 => Ok(#name::#idents),)*
+                    x => Err(format!(#unknown, x).into())
+                }
+            },
+            quote::quote! {
+                matches!(*ty, tokio_postgres::types::Type::INT2 | tokio_postgres::types::Type::INT4 | tokio_postgres::types::Type::INT8)
+            }
+        ),
+    };
+
+    Ok(quote::quote! {
+        impl tokio_postgres::types::ToSql for #name {
+            fn to_sql(
+                &self,
+                ty: &tokio_postgres::types::Type,
+                out: &mut dawnorm::BytesMut
+            ) -> ::std::result::Result<tokio_postgres::types::IsNull, Box<dyn ::std::error::Error + Sync + Send>> {
+                use tokio_postgres::types::ToSql;
+                #to_sql
+            }
+
+            fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+                #accepts
+            }
+
+            tokio_postgres::types::to_sql_checked!();
+        }
+
+        impl<'a> tokio_postgres::types::FromSql<'a> for #name {
+            fn from_sql(
+                ty: &tokio_postgres::types::Type,
+                raw: &'a [u8]
+            ) -> ::std::result::Result<Self, Box<dyn ::std::error::Error + Sync + Send>> {
+                #from_sql
+            }
+
+            fn accepts(ty: &tokio_postgres::types::Type) -> bool {
+                #accepts
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{generate_pg_enum_code, rename};
+    use proc_macro2::Span;
+
+    #[test]
+    fn rename_test() {
+        let span = Span::call_site();
+        assert_eq!(rename("InReview", "snake_case", span).unwrap(), "in_review");
+        assert_eq!(rename("InReview", "SCREAMING_SNAKE_CASE", span).unwrap(), "IN_REVIEW");
+        assert_eq!(rename("InReview", "kebab-case", span).unwrap(), "in-review");
+        assert_eq!(rename("InReview", "camelCase", span).unwrap(), "inReview");
+        assert_eq!(rename("InReview", "lowercase", span).unwrap(), "inreview");
+        assert!(rename("InReview", "Title Case", span).is_err());
+    }
+
+    #[test]
+    fn pg_enum_test() {
+        let ts = quote::quote!(
+            pub enum PostState {
+                Draft,
+                #[dawnorm(rename = "live")]
+                Published
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_pg_enum_code(&ast).to_string();
+
+        assert!(out.contains(r#"PostState :: Draft => "draft" , PostState :: Published => "live" ,"#));
+        assert!(out.contains(r#"ty . name () == "post_state""#));
+
+        let ts = quote::quote!(
+            #[dawnorm(int)]
+            pub enum Priority {
+                Low = 1,
+                High = 10
+            }
+        );
+
+        let ast: syn::DeriveInput = syn::parse2(ts).expect("Couldn't parse item");
+
+        let out = generate_pg_enum_code(&ast).to_string();
+
+        assert!(out.contains("x if x == Priority :: High as i64 => Ok (Priority :: High) ,"));
+        assert!(out.contains("i16 :: try_from (value) . map_err (out_of_range) ?"));
+        assert!(out.contains("out : & mut dawnorm :: BytesMut"));
+    }
+}
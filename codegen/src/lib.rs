@@ -1,9 +1,27 @@
 use proc_macro::TokenStream;
-use syn::DeriveInput;
+use syn::{parse_macro_input, DeriveInput};
 
-#[proc_macro_derive(Entity, attributes(key, key_noinsert, key_noinsert, key_noinsert_noupdate, noinsert_noupdate, noupdate, noinsert))]
+#[proc_macro_derive(Entity, attributes(key, key_noinsert, key_noinsert_noupdate, noinsert_noupdate, noupdate, noinsert, dawnorm))]
 pub fn postgres_entity(input: TokenStream) -> TokenStream {
-    let ast: DeriveInput = syn::parse(input).expect("Couldn't parse item");
+    let ast = parse_macro_input!(input as DeriveInput);
     //let table_name = parse_table_attr(&ast);
     dawnorm_codegen_lib::generate_entity_code(&ast).into()
-}
\ No newline at end of file
+}
+
+#[proc_macro_derive(Projection, attributes(dawnorm))]
+pub fn postgres_projection(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    dawnorm_codegen_lib::generate_projection_code(&ast).into()
+}
+
+#[proc_macro_derive(FromRow, attributes(dawnorm))]
+pub fn postgres_from_row(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    dawnorm_codegen_lib::generate_from_row_code(&ast).into()
+}
+
+#[proc_macro_derive(PgEnum, attributes(dawnorm))]
+pub fn postgres_enum(input: TokenStream) -> TokenStream {
+    let ast = parse_macro_input!(input as DeriveInput);
+    dawnorm_codegen_lib::generate_pg_enum_code(&ast).into()
+}
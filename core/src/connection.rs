@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use tokio_postgres::{Client, Row, Transaction, types::ToSql};
+
+use crate::{Error, FromRow};
+
+// What a query is sent on, the shared client of a context or a transaction
+#[derive(Clone, Copy)]
+pub enum Connection<'a> {
+    Client(&'a Client),
+    Transaction(&'a Transaction<'a>)
+}
+
+impl<'a> Connection<'a> {
+    pub async fn query(self, sql: &str, parms: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, Error> {
+        Ok(match self {
+            Connection::Client(x) => x.query(sql, parms).await?,
+            Connection::Transaction(x) => x.query(sql, parms).await?
+        })
+    }
+
+    pub async fn query_one(self, sql: &str, parms: &[&(dyn ToSql + Sync)]) -> Result<Row, Error> {
+        Ok(match self {
+            Connection::Client(x) => x.query_one(sql, parms).await?,
+            Connection::Transaction(x) => x.query_one(sql, parms).await?
+        })
+    }
+
+    pub async fn execute(self, sql: &str, parms: &[&(dyn ToSql + Sync)]) -> Result<u64, Error> {
+        Ok(match self {
+            Connection::Client(x) => x.execute(sql, parms).await?,
+            Connection::Transaction(x) => x.execute(sql, parms).await?
+        })
+    }
+
+    pub async fn batch_execute(self, sql: &str) -> Result<(), Error> {
+        match self {
+            Connection::Client(x) => x.batch_execute(sql).await?,
+            Connection::Transaction(x) => x.batch_execute(sql).await?
+        }
+        Ok(())
+    }
+
+    pub async fn query_as<T: FromRow>(self, sql: &str, parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Result<Vec<T>, Error> {
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.query(sql, ps.as_slice()).await?;
+        row.iter().map(T::from_row).collect()
+    }
+}
+
+// Owner of the connection of a DbSet: Arc<Client> for the shared client of a context,
+// &Transaction for sets moved into a transaction with DbSet::in_transaction
+pub trait Executor: Send + Sync {
+    fn connection(&self) -> Connection<'_>;
+}
+
+impl Executor for Arc<Client> {
+    fn connection(&self) -> Connection<'_> {
+        Connection::Client(self)
+    }
+}
+
+impl<'t, 'c> Executor for &'t Transaction<'c> {
+    fn connection(&self) -> Connection<'_> {
+        Connection::Transaction(self)
+    }
+}
@@ -1,19 +1,59 @@
 use std::sync::Arc;
 
-use tokio_postgres::{Client, types::ToSql};
+use tokio_postgres::{Client, Row, Transaction, types::{FromSql, ToSql}};
 
-use crate::{Entity, Error};
+use crate::{connection::Executor, identity::{identity_key, EntityCache, TypedCache}, not_deleted, IdentityMap, Cursor, Entity, Error, Expr, Field, FieldTuple, FromRow, Numeric, Page, Paged, Projection, Relation, Tracked, Tracking, Writable};
 
 #[macro_export]
 macro_rules! dbset {
     // This macro takes an argument of designator `ident` and
     // creates a function named `$func_name`.
-    // The `ident` designator is used for variable/function names.
-    ($table_name:ident, $type:ident) => {
+    // The `ident` designator is used for variable/function names,
+    // the entity is a type so generic entities such as Metric<f64> can be used.
+    ($table_name:ident, $type:ty) => {
         pub fn $table_name(&self) -> dawnorm::context::DbSet<$type> {
             dawnorm::context::DbSet::new(self.client.clone(), stringify!($table_name).into())
         }
     };
+    // The third argument names an IdentityMap field of the context caching the entities.
+    ($table_name:ident, $type:ty, $identity_map:ident) => {
+        pub fn $table_name(&self) -> dawnorm::context::DbSet<$type> {
+            dawnorm::context::DbSet::new(self.client.clone(), stringify!($table_name).into())
+                .identity_map(&self.$identity_map)
+        }
+    };
+    // The third argument is the table when it differs from the function name,
+    // e.g. a table in another schema such as "shop.orders".
+    ($name:ident, $type:ty, $table_name:literal) => {
+        pub fn $name(&self) -> dawnorm::context::DbSet<$type> {
+            dawnorm::context::DbSet::new(self.client.clone(), $table_name.into())
+        }
+    };
+}
+#[macro_export]
+macro_rules! raw_sql {
+    // Adds query_as and execute_sql to a context holding a `client`,
+    // next to the dbsets created with dbset!.
+    () => {
+        pub async fn query_as<T: dawnorm::FromRow>(&self, sql: &str, parms: Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>) -> Result<Vec<T>, dawnorm::Error> {
+            dawnorm::context::query_as(&self.client, sql, parms).await
+        }
+
+        pub async fn execute_sql(&self, sql: &str, parms: Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>) -> Result<u64, dawnorm::Error> {
+            dawnorm::context::execute_sql(&self.client, sql, parms).await
+        }
+    };
+}
+#[macro_export]
+macro_rules! change_tracker {
+    // Adds save_changes to a context with the given ChangeTracker field. The changes are
+    // written in a transaction on a client only this call uses, e.g. a dedicated
+    // connection or one taken from a pool, never the client shared by the dbsets.
+    ($tracker:ident) => {
+        pub async fn save_changes(&self, client: &mut tokio_postgres::Client) -> Result<u64, dawnorm::Error> {
+            self.$tracker.save_changes(client).await
+        }
+    };
 }
 #[macro_export]
 macro_rules! parms {
@@ -25,6 +65,24 @@ macro_rules! parms {
 }
 
 
+pub async fn query_as<T: FromRow>(client: &Client, sql: &str, parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Result<Vec<T>, crate::Error> {
+    let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+    let row = client.query(sql, ps.as_slice()).await?;
+    row.iter().map(T::from_row).collect()
+}
+
+pub async fn execute_sql(client: &Client, sql: &str, parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Result<u64, crate::Error> {
+    let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+    Ok(client.execute(sql, ps.as_slice()).await?)
+}
+
+#[derive(PartialEq)]
+enum DeletedScope {
+    Exclude,
+    Include,
+    Only
+}
+
 #[derive(PartialEq)]
 pub enum Ordering {
     ASC,
@@ -36,26 +94,65 @@ pub struct DbSetOrdering {
     ordering: Ordering
 }
 
-pub struct DbSet<T: Entity> {
-    client: Arc<Client>,
+pub struct DbSet<T: Entity, E: Executor = Arc<Client>> {
+    client: E,
     phantom: std::marker::PhantomData<T>,
     table_name: String,
+    alias: Option<String>,
     skip: Option<usize>,
     take: Option<usize>,
     filter: Option<(String, Vec<Box<dyn ToSql + Send + Sync>>)>,
     ordering: Vec<DbSetOrdering>,
+    grouping: Vec<&'static str>,
+    having: Option<(String, Vec<Box<dyn ToSql + Send + Sync>>)>,
+    joins: Vec<String>,
+    includes: Vec<Relation<T>>,
+    lock: Option<&'static str>,
+    lock_wait: Option<&'static str>,
+    deleted: DeletedScope,
+    cache: Option<Arc<dyn EntityCache<T>>>,
+    // in a transaction the map is only used to evict the entities it writes or reads
+    evict_only: bool,
+    key: Option<String>,
 }
 
-impl<T: Entity> DbSet<T> {
-    pub fn new(client: Arc<Client>, table_name: String) -> Self {
+impl<T: Entity, E: Executor> DbSet<T, E> {
+    pub fn new(client: E, table_name: String) -> Self {
         Self {
             client,
             phantom: std::marker::PhantomData,
             table_name,
+            alias: None,
             skip: None,
             take: None,
             filter: None,
-            ordering: Vec::new()
+            ordering: Vec::new(),
+            grouping: Vec::new(),
+            having: None,
+            joins: Vec::new(),
+            includes: Vec::new(),
+            lock: None,
+            lock_wait: None,
+            deleted: DeletedScope::Exclude,
+            cache: None,
+            evict_only: false,
+            key: None
+        }
+    }
+
+    pub(crate) fn table_name(&self) -> &str {
+        &self.table_name
+    }
+
+    // the name columns are qualified with, the alias when one is set
+    fn qualifier(&self) -> &str {
+        self.alias.as_deref().unwrap_or(&self.table_name)
+    }
+
+    fn source(&self) -> String {
+        match &self.alias {
+            Some(alias) => format!("{} {}", self.table_name, alias),
+            None => self.table_name.clone()
         }
     }
 
@@ -65,40 +162,181 @@ impl<T: Entity> DbSet<T> {
         self
     }
 
+    // names the table in the query, e.g. to join a table with itself
+    pub fn alias<S: Into<String>>(mut self, alias: S) -> Self {
+        self.alias = Some(alias.into());
+        self
+    }
+
     pub fn take(mut self, take: usize) -> Self {
         self.take = Some(take);
         self
     }
 
-    pub fn order_by(mut self, field: &'static str, ord: Ordering) -> Self {
-        self.ordering.push(DbSetOrdering { name: field, ordering: ord });
+    pub fn order_by<F: Into<&'static str>>(mut self, field: F, ord: Ordering) -> Self {
+        self.ordering.push(DbSetOrdering { name: field.into(), ordering: ord });
+        self
+    }
+
+    pub fn group_by<F: Into<&'static str>>(mut self, field: F) -> Self {
+        self.grouping.push(field.into());
+        self
+    }
+
+    // parameters of the having clause are numbered after the ones of the filter
+    pub fn having<S: Into<String>>(mut self, having: S, parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Self {
+        self.having = Some((having.into(), parms));
         self
     }
 
     pub fn filter<S: Into<String>>(mut self, filter: S, parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Self {
         self.filter = Some((filter.into(), parms));
+        self.key = None;
         self
     }
 
-    pub fn filter_pk(mut self, parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Self {
-        self.filter = Some((T::sql_key_constrint().to_string(), parms));
+    // field->>key = value on a JSON or JSONB column, the key is sent as a parameter.
+    // Added to the filter with AND, unlike filter which replaces it
+    #[cfg(feature = "serde")]
+    pub fn filter_json_text<V: crate::JsonColumn, S: Into<String>>(self, field: Field<V>, key: &str, value: S) -> Self {
+        self.and_filter(
+            |n| format!("{}->>${} = ${}", field, n + 1, n + 2),
+            crate::parms![key.to_string(), value.into()]
+        )
+    }
+
+    // field @> value on a JSONB column, value is any serializable document.
+    // Added to the filter with AND, unlike filter which replaces it
+    #[cfg(feature = "serde")]
+    pub fn filter_json_contains<V, J>(self, field: Field<V>, value: J) -> Self
+    where
+        V: crate::JsonColumn,
+        J: serde::Serialize + std::fmt::Debug + Send + Sync + 'static
+    {
+        self.and_filter(|n| format!("{} @> ${}", field, n + 1), crate::parms![crate::Json(value)])
+    }
+
+    // the condition gets the count of the parameters already in the filter to number its own after them
+    #[cfg(feature = "serde")]
+    fn and_filter<F: FnOnce(usize) -> String>(mut self, condition: F, mut parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Self {
+        match self.filter.take() {
+            Some((filter, mut existing)) => {
+                let condition = condition(existing.len());
+                existing.append(&mut parms);
+                self.filter(format!("({}) AND {}", filter, condition), existing)
+            },
+            None => {
+                let condition = condition(0);
+                self.filter(condition, parms)
+            }
+        }
+    }
+
+    pub fn with_deleted(mut self) -> Self {
+        self.deleted = DeletedScope::Include;
+        self
+    }
+
+    pub fn only_deleted(mut self) -> Self {
+        self.deleted = DeletedScope::Only;
         self
     }
 
+    fn deleted_condition(&self) -> Option<String> {
+        match self.deleted {
+            DeletedScope::Exclude => not_deleted::<T>(self.qualifier()),
+            DeletedScope::Include => None,
+            DeletedScope::Only => T::sql_soft_delete_field().map(|x| format!("{}.{} IS NOT NULL", self.qualifier(), x))
+        }
+    }
+
+    fn check_lock(&self) -> Result<(), crate::Error> {
+        if self.lock.is_none() && self.lock_wait.is_some() {
+            return Err(Error::InvalidQuery("skip_locked and nowait require for_update or for_share".into()));
+        }
+        Ok(())
+    }
+
+    pub fn include(mut self, relation: fn() -> Relation<T>) -> Self {
+        self.includes.push(relation());
+        self
+    }
+
+    async fn load_includes(&self, parents: &mut [T]) -> Result<(), crate::Error> {
+        for relation in &self.includes {
+            relation.load(self.client.connection(), parents).await?;
+        }
+        Ok(())
+    }
+
+    fn join_condition<U: Entity, F: Executor>(other: &DbSet<U, F>, on: String) -> String {
+        match other.deleted_condition() {
+            Some(deleted) => format!("({}) AND {}", on, deleted),
+            None => on
+        }
+    }
+
+    pub fn inner_join<U: Entity, F: Executor, S: Into<String>>(mut self, other: DbSet<U, F>, on: S) -> JoinDbSet<T, U, E> {
+        let on = Self::join_condition(&other, on.into());
+        self.joins.push(format!("INNER JOIN {} ON {}", other.source(), on));
+        JoinDbSet::new(self, other.qualifier().into())
+    }
+
+    pub fn left_join<U: Entity, F: Executor, S: Into<String>>(mut self, other: DbSet<U, F>, on: S) -> JoinDbSet<T, Option<U>, E> {
+        let on = Self::join_condition(&other, on.into());
+        self.joins.push(format!("LEFT JOIN {} ON {}", other.source(), on));
+        JoinDbSet::new(self, other.qualifier().into())
+    }
+
     fn select_query(&mut self, single: bool) -> (String, Vec<Box<dyn ToSql + Send + Sync>>) {
         self.select_query_with_fields(single, T::sql_fields())
     }
 
+    fn where_clause(&self) -> String {
+        match (&self.filter, self.deleted_condition()) {
+            (Some((filter, _)), Some(deleted)) => format!("WHERE ({}) AND {}", filter, deleted),
+            (Some((filter, _)), None) => format!("WHERE {}", filter),
+            (None, Some(deleted)) => format!("WHERE {}", deleted),
+            (None, None) => "".into()
+        }
+    }
+
+    fn filter_clause(&mut self) -> (String, Vec<Box<dyn ToSql + Send + Sync>>) {
+        let filt = self.where_clause();
+        match self.filter.take() {
+            Some((_, parms)) => (filt, parms),
+            None => (filt, Vec::new())
+        }
+    }
+
     fn select_query_with_fields(&mut self, single: bool, fields: &str) -> (String, Vec<Box<dyn ToSql + Send + Sync>>) {
-        let mut parms : Vec<Box<dyn ToSql + Send + Sync>>  = Vec::new();
-        let filt = if self.filter.is_some() {
-            let filter = self.filter.take().unwrap();
-            parms = filter.1;
-            format!("WHERE {}", &filter.0)
+        let (from, parms) = self.source_clause();
+        (self.select_from(single, fields, &from), parms)
+    }
+
+    // source with joins, where, group by and having, shared by a select and its count
+    fn source_clause(&mut self) -> (String, Vec<Box<dyn ToSql + Send + Sync>>) {
+        let (mut filt, mut parms) = self.filter_clause();
+
+        if !self.grouping.is_empty() {
+            filt.push_str(&format!(" GROUP BY {}", self.grouping.join(", ")));
+        }
+
+        if let Some((having, having_parms)) = self.having.take() {
+            filt.push_str(&format!(" HAVING {}", having));
+            parms.extend(having_parms);
+        }
+
+        let source = if self.joins.is_empty() {
+            self.source()
         } else {
-            "".into()
+            format!("{} {}", self.source(), self.joins.join(" "))
         };
 
+        (format!("{} {}", source, filt), parms)
+    }
+
+    fn select_from(&self, single: bool, fields: &str, from: &str) -> String {
         let order : String = if !self.ordering.is_empty() {
             let mut o : String = "ORDER BY ".into(); 
             for or in &self.ordering {
@@ -120,23 +358,48 @@ impl<T: Entity> DbSet<T> {
         } else {
             "".into()
         };
-        
+
+        let lock = match self.lock {
+            Some(x) => format!("{} {}", x, self.lock_wait.unwrap_or("")),
+            None => "".into()
+        };
+
         if single {
-            (format!("SELECT {} FROM {} {} {} LIMIT 1;", fields, &self.table_name, filt, order), parms)
+            format!("SELECT {} FROM {} {} LIMIT 1 {};", fields, from, order, lock)
         } else {
-            (format!("SELECT {} FROM {} {} {} {} {};", fields, &self.table_name, filt, order, take, skip), parms)
+            format!("SELECT {} FROM {} {} {} {} {};", fields, from, order, take, skip, lock)
+        }
+    }
+
+    // with group_by count gives the number of groups, the other aggregates are
+    // per group and taken with select_tuple instead
+    fn aggregate_query(&mut self, aggregate: &str) -> Result<(String, Vec<Box<dyn ToSql + Send + Sync>>), crate::Error> {
+        let grouped = !self.grouping.is_empty();
+        let (from, parms) = self.source_clause();
+        match (grouped, aggregate) {
+            (false, _) => Ok((format!("SELECT {} FROM {};", aggregate, from), parms)),
+            (true, "COUNT(*)") => Ok((format!("SELECT COUNT(*) FROM (SELECT 1 FROM {}) __dawnorm_count;", from), parms)),
+            (true, _) => Err(Error::InvalidQuery(format!("{} can't be combined with group_by, use select_tuple", aggregate)))
         }
     }
 
     // **** CRUD fucntions **** \\
     pub async fn try_first(mut self) -> Result<Option<T>, crate::Error> {
+        if let Some(cached) = self.cached() {
+            return Ok(Some(cached));
+        }
+        self.check_lock()?;
         let (query, parms) = self.select_query(true);
         let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
-        let mut row = self.client.query(&query, ps.as_slice()).await?;
+        let mut row = self.client.connection().query(&query, ps.as_slice()).await?;
         Ok(match row.len() {
             0 => None,
             1 => {
-                Some(T::from_row(row.pop().unwrap())?)
+                let mut res = [T::from_row(&row.pop().unwrap())?];
+                self.load_includes(&mut res).await?;
+                self.remember(&res);
+                let [res] = res;
+                Some(res)
             },
             _ => panic!("this should never happen with first")
         })
@@ -157,50 +420,299 @@ impl<T: Entity> DbSet<T> {
     }
 
     pub async fn to_vec(mut self) -> Result<Vec<T>, crate::Error> {
+        self.check_lock()?;
         let (query, parms) = self.select_query(false);
         let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
-        let row = self.client.query(&query, ps.as_slice()).await?;
-        let res : Result<Vec<T>, crate::Error> = row.into_iter().map(|x| T::from_row(x)).collect();
-        Ok(res?)
+        let row = self.client.connection().query(&query, ps.as_slice()).await?;
+        let mut res = row.iter().map(T::from_row).collect::<Result<Vec<T>, crate::Error>>()?;
+        self.load_includes(&mut res).await?;
+        self.remember(&res);
+        Ok(res)
+    }
+
+    pub async fn select<P: Projection>(mut self) -> Result<Vec<P>, crate::Error> {
+        self.check_lock()?;
+        let (query, parms) = self.select_query_with_fields(false, P::sql_fields());
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.client.connection().query(&query, ps.as_slice()).await?;
+        row.iter().map(P::from_row).collect()
+    }
+
+    pub async fn select_tuple<F: FieldTuple>(mut self, fields: F) -> Result<Vec<F::Output>, crate::Error> {
+        self.check_lock()?;
+        let (query, parms) = self.select_query_with_fields(false, &fields.sql_fields());
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.client.connection().query(&query, ps.as_slice()).await?;
+        row.iter().map(F::from_row).collect()
+    }
+
+    async fn aggregate<R: for<'a> FromSql<'a>>(mut self, aggregate: Expr<R>) -> Result<R, crate::Error> {
+        let (query, parms) = self.aggregate_query(aggregate.sql())?;
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.client.connection().query_one(&query, ps.as_slice()).await?;
+        Ok(row.try_get::<usize, R>(0)?)
+    }
+
+    pub async fn count(self) -> Result<u64, crate::Error> {
+        let count = self.aggregate(crate::count()).await?;
+        Ok(count as u64)
+    }
+
+    pub async fn sum<V: Numeric>(self, field: Field<V>) -> Result<Option<V::Sum>, crate::Error> {
+        self.aggregate(field.sum()).await
+    }
+
+    pub async fn avg<V: Numeric>(self, field: Field<V>) -> Result<Option<f64>, crate::Error> {
+        self.aggregate(field.avg()).await
+    }
+
+    pub async fn min<V: for<'a> FromSql<'a>>(self, field: Field<V>) -> Result<Option<V>, crate::Error> {
+        self.aggregate(field.min()).await
+    }
+
+    pub async fn max<V: for<'a> FromSql<'a>>(self, field: Field<V>) -> Result<Option<V>, crate::Error> {
+        self.aggregate(field.max()).await
+    }
+
+    pub async fn from_sql(&self, sql: &str, parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Result<Vec<T>, crate::Error> {
+        self.client.connection().query_as(sql, parms).await
+    }
+
+    // pages are zero based, the total is taken from the page itself and only
+    // counted separately when the page is past the end
+    pub async fn paginate(mut self, page: usize, page_size: usize) -> Result<Paged<T>, crate::Error> {
+        self.check_lock()?;
+        self.skip = match page.checked_mul(page_size) {
+            Some(x) => Some(x),
+            None => return Err(Error::InvalidQuery("paginate page * page_size overflows".into()))
+        };
+        self.take = Some(page_size);
+        let (query, count_query, parms) = self.paginate_queries();
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.client.connection().query(&query, ps.as_slice()).await?;
+
+        let total = match row.first() {
+            Some(x) => x.try_get::<&str, i64>("__dawnorm_total")?,
+            None => self.client.connection().query_one(&count_query, ps.as_slice()).await?.try_get::<usize, i64>(0)?
+        } as u64;
+
+        let mut items = row.iter().map(T::from_row).collect::<Result<Vec<T>, crate::Error>>()?;
+        self.load_includes(&mut items).await?;
+        Ok(Paged {
+            items,
+            total,
+            page,
+            page_size,
+            total_pages: if page_size == 0 { 0 } else { total.div_ceil(page_size as u64) }
+        })
+    }
+
+    // the page and, for pages past the end, the count over the same clauses and parameters
+    fn paginate_queries(&mut self) -> (String, String, Vec<Box<dyn ToSql + Send + Sync>>) {
+        let fields = format!("{}, COUNT(*) OVER() AS __dawnorm_total", T::sql_fields());
+        let (from, parms) = self.source_clause();
+        let query = self.select_from(false, &fields, &from);
+        let count_query = format!("SELECT COUNT(*) FROM (SELECT 1 FROM {}) __dawnorm_count;", from);
+        (query, count_query, parms)
+    }
+
+    // seeks past the cursor using the order_by columns, which have to be
+    // plain columns of the table, all in the same direction and unique together
+    pub async fn page_after(mut self, cursor: Option<&Cursor>, size: usize) -> Result<Page<T>, crate::Error> {
+        self.check_lock()?;
+        if self.ordering.is_empty() {
+            return Err(Error::InvalidQuery("page_after requires order_by".into()));
+        }
+        if self.ordering.iter().any(|x| x.ordering != self.ordering[0].ordering) {
+            return Err(Error::InvalidQuery("page_after requires all order_by columns in the same direction".into()));
+        }
+
+        let columns = self.ordering.iter().map(|x| x.name).collect::<Vec<&str>>();
+
+        if let Some(cursor) = cursor {
+            let (filter, mut parms) = self.filter.take().unwrap_or(("TRUE".into(), Vec::new()));
+            parms.push(Box::new(cursor.decode()?));
+            let seek = format!(
+                "({}) {} (SELECT {} FROM json_populate_record(NULL::{}, ${}::text::json) c)",
+                columns.join(", "),
+                if self.ordering[0].ordering == Ordering::ASC { ">" } else { "<" },
+                columns.iter().map(|x| format!("c.{}", x)).collect::<Vec<String>>().join(", "),
+                self.table_name,
+                parms.len()
+            );
+            self.filter = Some((format!("({}) AND {}", filter, seek), parms));
+        }
+
+        let fields = format!(
+            "{}, json_build_object({})::text AS __dawnorm_cursor",
+            T::sql_fields(),
+            columns.iter().map(|x| format!("'{0}', {0}", x)).collect::<Vec<String>>().join(", ")
+        );
+
+        self.skip = None;
+        self.take = Some(size + 1);
+        let (query, parms) = self.select_query_with_fields(false, &fields);
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let mut row = self.client.connection().query(&query, ps.as_slice()).await?;
+
+        let next = if row.len() > size {
+            row.truncate(size);
+            match row.last() {
+                Some(x) => Some(Cursor::encode(x.try_get::<&str, &str>("__dawnorm_cursor")?)),
+                None => None
+            }
+        } else {
+            None
+        };
+
+        let mut items = row.iter().map(T::from_row).collect::<Result<Vec<T>, crate::Error>>()?;
+        self.load_includes(&mut items).await?;
+        Ok(Page { items, next })
+    }
+
+    pub async fn refresh_materialized_view(&self, concurrently: bool) -> Result<(), crate::Error> {
+        let concurrently = if concurrently { " CONCURRENTLY" } else { "" };
+        let query = format!("REFRESH MATERIALIZED VIEW{} {};", concurrently, self.table_name);
+        self.client.connection().batch_execute(&query).await?;
+        self.forget_all();
+        Ok(())
+    }
+}
+
+// **** Transactions **** \\
+impl<T: Entity> DbSet<T> {
+    // the same query sent on a transaction instead of the shared client. Entities are
+    // evicted from the identity map instead of cached since the transaction may still be
+    // rolled back, rows read by other sets before the commit may be cached again
+    pub fn in_transaction<'t, 'c>(self, transaction: &'t Transaction<'c>) -> DbSet<T, &'t Transaction<'c>> {
+        DbSet {
+            client: transaction,
+            phantom: std::marker::PhantomData,
+            table_name: self.table_name,
+            alias: self.alias,
+            skip: self.skip,
+            take: self.take,
+            filter: self.filter,
+            ordering: self.ordering,
+            grouping: self.grouping,
+            having: self.having,
+            joins: self.joins,
+            includes: self.includes,
+            lock: self.lock,
+            lock_wait: self.lock_wait,
+            deleted: self.deleted,
+            cache: self.cache,
+            evict_only: true,
+            key: self.key
+        }
+    }
+}
+
+// **** Row locks **** \\
+// only sets running on a transaction can lock rows, the locks are held until it ends
+impl<'t, 'c, T: Entity> DbSet<T, &'t Transaction<'c>> {
+    pub fn for_update(mut self) -> Self {
+        self.lock = Some("FOR UPDATE");
+        self
+    }
+
+    pub fn for_share(mut self) -> Self {
+        self.lock = Some("FOR SHARE");
+        self
+    }
+
+    pub fn skip_locked(mut self) -> Self {
+        self.lock_wait = Some("SKIP LOCKED");
+        self
+    }
+
+    pub fn nowait(mut self) -> Self {
+        self.lock_wait = Some("NOWAIT");
+        self
+    }
+}
+
+// **** Write fucntions **** \\
+impl<T: Writable, E: Executor> DbSet<T, E> {
+    pub fn filter_pk(mut self, parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Self {
+        self.key = Some(format!("{:?}", parms));
+        self.filter = Some((T::sql_key_constrint().to_string(), parms));
+        self
+    }
+
+    pub async fn find(self, key: T::Key) -> Result<Option<T>, crate::Error> {
+        self.filter_pk(T::key_parms(key)).try_first().await
+    }
+
+    pub async fn find_many(self, keys: Vec<T::Key>) -> Result<Vec<T>, crate::Error> {
+        if keys.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (filter, parms) = T::sql_key_filter(keys);
+        self.filter(filter, parms).to_vec().await
     }
 
     pub async fn insert(&self, obj: T) -> Result<T, crate::Error> {
         let (query, parms) = T::get_insert_query(obj, &self.table_name);
         let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
-        let mut row = self.client.query(&query, ps.as_slice()).await?;
-        Ok(match row.len() {
+        let mut row = self.client.connection().query(&query, ps.as_slice()).await?;
+        let res = match row.len() {
             1 => {
-                T::from_row(row.pop().unwrap())?
+                T::from_row(&row.pop().unwrap())?
             },
             _ => panic!("this should never happen with insert")
-        })
+        };
+        self.remember(std::slice::from_ref(&res));
+        Ok(res)
     }
 
     pub async fn update(&self, obj: T) -> Result<T, crate::Error> {
         let (query, parms) = T::get_update_query(obj, &self.table_name);
         let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
-        let mut row = self.client.query(&query, ps.as_slice()).await?;
-        Ok(match row.len() {
+        let mut row = self.client.connection().query(&query, ps.as_slice()).await?;
+        let res = match row.len() {
             1 => {
-                T::from_row(row.pop().unwrap())?
+                T::from_row(&row.pop().unwrap())?
             },
             _ => panic!("this should never happen with insert")
-        })
+        };
+        self.remember(std::slice::from_ref(&res));
+        Ok(res)
     }
 
     pub async fn delete(&self, obj: &T) -> Result<bool, crate::Error> {
         let (query, parms) = T::get_delete_query(obj, &self.table_name);
         let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
-        let ret = self.client.execute(&query, ps.as_slice()).await?;
+        let ret = self.client.connection().execute(&query, ps.as_slice()).await?;
+        self.forget(obj);
         Ok(ret == 1)
     }
 
-    pub async fn exec_delete<U: ToSql + Sync + 'static>(mut self) -> Result<u64, crate::Error> {
+    pub async fn delete_by_key(self, key: T::Key) -> Result<bool, crate::Error> {
+        Ok(self.filter_pk(T::key_parms(key)).delete_filtered().await? == 1)
+    }
+
+    pub async fn exists_by_key(self, key: T::Key) -> Result<bool, crate::Error> {
+        self.filter_pk(T::key_parms(key)).any().await
+    }
+
+    pub async fn exec_delete<U: ToSql + Sync + 'static>(self) -> Result<u64, crate::Error> {
+        self.delete_filtered().await
+    }
+
+    async fn delete_filtered(mut self) -> Result<u64, crate::Error> {
         if self.filter.is_none() { panic!("filter must be set") }
         let filter = self.filter.take().unwrap();
         let ps : Vec<&(dyn ToSql + Sync)> = filter.1.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
-        let query = &format!("DELETE FROM {} WHERE {};", self.table_name, filter.0);
-        let row = self.client.execute(query, ps.as_slice()).await?;
+        let query = &match T::sql_soft_delete_field() {
+            Some(x) => format!("UPDATE {} SET {} = now() WHERE ({}) AND {} IS NULL;", self.source(), x, filter.0, x),
+            None => format!("DELETE FROM {} WHERE {};", self.source(), filter.0)
+        };
+        let row = self.client.connection().execute(query, ps.as_slice()).await?;
+        match (self.cache.as_ref(), self.key.as_ref()) {
+            (Some(cache), Some(key)) => cache.remove(key),
+            _ => self.forget_all()
+        }
         Ok(row)
     }
 
@@ -209,9 +721,464 @@ impl<T: Entity> DbSet<T> {
         let filter = self.filter.take().unwrap();
         let mut ps : Vec<&(dyn ToSql + Sync)> = filter.1.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
         ps.push(&value);
-        let query = &format!("UPDATE {} SET {} = ${} WHERE {};", self.table_name, field, ps.len(), filter.0);
-        let row = self.client.execute(query, ps.as_slice()).await?;
+        let query = &format!("UPDATE {} SET {} = ${} WHERE {};", self.source(), field, ps.len(), filter.0);
+        let row = self.client.connection().execute(query, ps.as_slice()).await?;
+        self.forget_all();
+        Ok(row)
+    }
+
+    pub async fn restore(mut self) -> Result<u64, crate::Error> {
+        let field = match T::sql_soft_delete_field() {
+            Some(x) => x,
+            None => return Err(Error::InvalidQuery("restore requires a soft_delete field".into()))
+        };
+        if self.filter.is_none() {
+            return Err(Error::InvalidQuery("restore requires a filter".into()));
+        }
+        self.deleted = DeletedScope::Only;
+        let (filt, parms) = self.filter_clause();
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let query = &format!("UPDATE {} SET {} = NULL {};", self.source(), field, filt);
+        let row = self.client.connection().execute(query, ps.as_slice()).await?;
+        self.forget_all();
+        Ok(row)
+    }
+
+    pub fn update_set(self) -> UpdateSet<T, E> {
+        UpdateSet::new(self)
+    }
+
+    pub(crate) fn forget(&self, obj: &T) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.remove(&identity_key(obj));
+        }
+    }
+
+    // evicts obj when called, for changes written later such as by ChangeTracker
+    pub(crate) fn forget_later(&self, obj: &T) -> Option<Box<dyn Fn() + Send + Sync>> {
+        self.cache.as_ref().map(|x| x.evict_later(obj))
+    }
+}
+
+// **** Change tracking **** \\
+impl<T: Tracking, E: Executor> DbSet<T, E> {
+    // includes are not loaded for tracked entities
+    pub async fn try_first_tracked(mut self) -> Result<Option<Tracked<T>>, crate::Error> {
+        self.check_lock()?;
+        let (query, parms) = self.select_query(true);
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.client.connection().query(&query, ps.as_slice()).await?;
+        row.first().map(Tracked::from_row).transpose()
+    }
+
+    pub async fn first_tracked(self) -> Result<Tracked<T>, crate::Error> {
+        match self.try_first_tracked().await? {
+            Some(x) => Ok(x),
+            None => Err(Error::NoResult)
+        }
+    }
+
+    pub async fn to_vec_tracked(mut self) -> Result<Vec<Tracked<T>>, crate::Error> {
+        self.check_lock()?;
+        let (query, parms) = self.select_query(false);
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.client.connection().query(&query, ps.as_slice()).await?;
+        row.iter().map(Tracked::from_row).collect()
+    }
+
+    // only the changed columns are written, without changes no query is sent
+    pub async fn update_tracked(&self, obj: Tracked<T>) -> Result<T, crate::Error> {
+        let (query, parms) = match obj.get_update_query(&self.table_name) {
+            Ok(x) => x,
+            Err(obj) => return Ok(obj)
+        };
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let mut row = self.client.connection().query(&query, ps.as_slice()).await?;
+        let res = match row.len() {
+            1 => T::from_row(&row.pop().unwrap())?,
+            _ => return Err(Error::NoResult)
+        };
+        self.remember(std::slice::from_ref(&res));
+        Ok(res)
+    }
+}
+
+// **** Identity map **** \\
+impl<T: Writable + Clone + Send + 'static, E: Executor> DbSet<T, E> {
+    // filter_pk lookups are answered from the map, loaded and saved entities are added to it;
+    // raw sql bypasses the map, call IdentityMap::clear after changing rows that way
+    pub fn identity_map(mut self, map: &IdentityMap) -> Self {
+        self.cache = Some(Arc::new(TypedCache::<T>::new(map, &self.table_name)));
+        self
+    }
+}
+
+impl<T: Entity, E: Executor> DbSet<T, E> {
+    fn cached(&self) -> Option<T> {
+        let cache = self.cache.as_ref()?;
+        let key = self.key.as_ref()?;
+        if self.evict_only || self.lock.is_some() || !self.includes.is_empty() || self.deleted != DeletedScope::Exclude ||
+            self.skip.is_some() || self.take.is_some() || self.alias.is_some() {
+            return None;
+        }
+        cache.get(key)
+    }
+
+    fn remember(&self, items: &[T]) {
+        if let Some(cache) = self.cache.as_ref() {
+            if self.evict_only {
+                items.iter().for_each(|x| cache.evict(x));
+            } else if self.deleted == DeletedScope::Exclude {
+                items.iter().for_each(|x| cache.put(x));
+            }
+        }
+    }
+
+    fn forget_all(&self) {
+        if let Some(cache) = self.cache.as_ref() {
+            cache.clear();
+        }
+    }
+}
+
+pub struct UpdateSet<T: Entity, E: Executor = Arc<Client>> {
+    set: DbSet<T, E>,
+    // columns with an sql expression, or a parameter when the expression is None
+    assignments: Vec<(&'static str, Option<String>)>,
+    parms: Vec<Box<dyn ToSql + Send + Sync>>,
+    invalid_fields: Vec<String>,
+}
+
+impl<T: Entity, E: Executor> UpdateSet<T, E> {
+    fn new(set: DbSet<T, E>) -> Self {
+        Self { set, assignments: Vec::new(), parms: Vec::new(), invalid_fields: Vec::new() }
+    }
+
+    fn check_field(&mut self, field: &str) {
+        if !T::sql_field_names().contains(&field) {
+            self.invalid_fields.push(field.into());
+        }
+    }
+
+    // **** Fluent fucntions **** \\
+    pub fn set<V: ToSql + Send + Sync + 'static>(mut self, field: Field<V>, value: V) -> Self {
+        self.check_field(field.name());
+        self.parms.push(Box::new(value));
+        self.assignments.push((field.name(), None));
+        self
+    }
+
+    pub fn set_expr<F: Into<&'static str>, S: Into<String>>(mut self, field: F, expr: S) -> Self {
+        let field = field.into();
+        self.check_field(field);
+        self.assignments.push((field, Some(expr.into())));
+        self
+    }
+
+    fn query(&mut self, returning: bool) -> Result<(String, Vec<Box<dyn ToSql + Send + Sync>>), crate::Error> {
+        if !self.invalid_fields.is_empty() {
+            return Err(Error::InvalidQuery(format!("unknown fields in update_set: {}", self.invalid_fields.join(", "))));
+        }
+        if self.assignments.is_empty() {
+            return Err(Error::InvalidQuery("update_set requires at least one set".into()));
+        }
+        let (filter, mut parms) = match self.set.filter.take() {
+            Some(x) => x,
+            None => return Err(Error::InvalidQuery("update_set requires a filter".into()))
+        };
+        let mut idx = parms.len();
+        let assignments = self.assignments.iter().map(|(field, expr)| match expr {
+            Some(expr) => format!("{} = {}", field, expr),
+            None => {
+                idx += 1;
+                format!("{} = ${}", field, idx)
+            }
+        }).collect::<Vec<String>>();
+        parms.append(&mut self.parms);
+
+        let returning = if returning {
+            format!(" RETURNING {}", T::sql_fields())
+        } else {
+            "".into()
+        };
+        Ok((format!("UPDATE {} SET {} WHERE {}{};", self.set.source(), assignments.join(", "), filter, returning), parms))
+    }
+
+    // **** CRUD fucntions **** \\
+    pub async fn exec(mut self) -> Result<u64, crate::Error> {
+        let (query, parms) = self.query(false)?;
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.set.client.connection().execute(&query, ps.as_slice()).await?;
+        self.set.forget_all();
         Ok(row)
     }
+
+    pub async fn exec_returning(mut self) -> Result<Vec<T>, crate::Error> {
+        let (query, parms) = self.query(true)?;
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.set.client.connection().query(&query, ps.as_slice()).await?;
+        self.set.forget_all();
+        row.iter().map(T::from_row).collect()
+    }
+}
+
+pub trait JoinTarget: Sized {
+    fn sql_fields(table_name: &str) -> String;
+    fn from_row(row: &Row, table_name: &str) -> Result<Self, crate::Error>;
+}
+
+impl<U: Entity> JoinTarget for U {
+    fn sql_fields(table_name: &str) -> String {
+        U::sql_table_fields(table_name)
+    }
+
+    fn from_row(row: &Row, table_name: &str) -> Result<Self, crate::Error> {
+        U::from_row_prefixed(row, &format!("{}.", table_name))
+    }
+}
+
+impl<U: Entity> JoinTarget for Option<U> {
+    fn sql_fields(table_name: &str) -> String {
+        // a whole row reference is null when the left join found no match
+        format!("{}, {} IS NULL AS \"{}.\"", U::sql_table_fields(table_name), table_name, table_name)
+    }
+
+    fn from_row(row: &Row, table_name: &str) -> Result<Self, crate::Error> {
+        if row.try_get::<&str, bool>(&format!("{}.", table_name))? {
+            Ok(None)
+        } else {
+            Ok(Some(U::from_row_prefixed(row, &format!("{}.", table_name))?))
+        }
+    }
+}
+
+pub struct JoinDbSet<T: Entity, U: JoinTarget, E: Executor = Arc<Client>> {
+    set: DbSet<T, E>,
+    phantom: std::marker::PhantomData<U>,
+    join_table_name: String,
+}
+
+impl<T: Entity, U: JoinTarget, E: Executor> JoinDbSet<T, U, E> {
+    fn new(set: DbSet<T, E>, join_table_name: String) -> Self {
+        Self { set, phantom: std::marker::PhantomData, join_table_name }
+    }
+
+    // **** Fluent fucntions **** \\
+    pub fn skip(mut self, skip: usize) -> Self {
+        self.set = self.set.skip(skip);
+        self
+    }
+
+    pub fn take(mut self, take: usize) -> Self {
+        self.set = self.set.take(take);
+        self
+    }
+
+    // columns have to be qualified with their table name or alias, e.g. posts.id
+    pub fn order_by<F: Into<&'static str>>(mut self, field: F, ord: Ordering) -> Self {
+        self.set = self.set.order_by(field, ord);
+        self
+    }
+
+    // columns have to be qualified with their table name or alias, e.g. posts.id
+    pub fn filter<S: Into<String>>(mut self, filter: S, parms: Vec<Box<dyn ToSql + Send + Sync>>) -> Self {
+        self.set = self.set.filter(filter, parms);
+        self
+    }
+
+    fn select_query(&mut self, single: bool) -> (String, Vec<Box<dyn ToSql + Send + Sync>>) {
+        let fields = format!("{}, {}", T::sql_table_fields(self.set.qualifier()), U::sql_fields(&self.join_table_name));
+        self.set.select_query_with_fields(single, &fields)
+    }
+
+    fn map_row(&self, row: &Row) -> Result<(T, U), crate::Error> {
+        Ok((
+            T::from_row_prefixed(row, &format!("{}.", self.set.qualifier()))?,
+            U::from_row(row, &self.join_table_name)?
+        ))
+    }
+
+    // **** CRUD fucntions **** \\
+    pub async fn try_first(mut self) -> Result<Option<(T, U)>, crate::Error> {
+        let (query, parms) = self.select_query(true);
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.set.client.connection().query(&query, ps.as_slice()).await?;
+        row.first().map(|x| self.map_row(x)).transpose()
+    }
+
+    pub async fn first(self) -> Result<(T, U), crate::Error> {
+        match self.try_first().await? {
+            Some(x) => Ok(x),
+            None => Err(Error::NoResult)
+        }
+    }
+
+    pub async fn to_vec(mut self) -> Result<Vec<(T, U)>, crate::Error> {
+        let (query, parms) = self.select_query(false);
+        let ps : Vec<&(dyn ToSql + Sync)> = parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+        let row = self.set.client.connection().query(&query, ps.as_slice()).await?;
+        row.iter().map(|x| self.map_row(x)).collect()
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use tokio_postgres::Row;
+
+    use crate::{connection::{Connection, Executor}, Entity, Error, Field, FromRow, IdentityMap};
+    use super::{DbSet, Ordering, UpdateSet};
+
+    struct Post;
+
+    impl FromRow for Post {
+        fn from_row_prefixed(_row: &Row, _prefix: &str) -> Result<Self, Error> {
+            Ok(Post)
+        }
+    }
+
+    impl Entity for Post {
+        fn sql_fields() -> &'static str {
+            "id, title"
+        }
+
+        fn sql_field_names() -> &'static [&'static str] {
+            &["id", "title"]
+        }
+
+        fn sql_table_fields(table_name: &str) -> String {
+            format!("{0}.id, {0}.title", table_name)
+        }
+    }
+
+    #[derive(dawnorm_codegen::Entity)]
+    struct Comment {
+        #[key_noinsert_noupdate]
+        id: i32,
+        #[dawnorm(soft_delete)]
+        deleted_at: Option<std::time::SystemTime>
+    }
+
+    #[derive(dawnorm_codegen::Entity, Clone)]
+    struct Author {
+        #[key]
+        id: i32,
+        name: String
+    }
+
+    // the queries are only built, never sent
+    struct NoConnection;
+
+    impl Executor for NoConnection {
+        fn connection(&self) -> Connection<'_> {
+            unreachable!()
+        }
+    }
+
+    fn posts() -> DbSet<Post, NoConnection> {
+        DbSet::new(NoConnection, "posts".into())
+    }
+
+    const TITLE: Field<String> = Field::new("title");
+
+    #[test]
+    fn update_set_test() {
+        let mut update = UpdateSet::new(posts().filter("id = $1", crate::parms![1])).set(TITLE, "new".to_string());
+        let (query, parms) = update.query(false).unwrap();
+        assert_eq!(query, "UPDATE posts SET title = $2 WHERE id = $1;");
+        assert_eq!(parms.len(), 2);
+
+        let mut update = UpdateSet::new(posts()).set(TITLE, "new".to_string());
+        assert!(matches!(update.query(false), Err(Error::InvalidQuery(_))));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn filter_json_test() {
+        let settings = Field::<crate::Json<serde_json::Value>>::new("settings");
+        let mut set = posts()
+            .filter("id > $1", crate::parms![1])
+            .filter_json_text(settings, "theme", "dark")
+            .filter_json_text(settings, "lang", "en");
+        let (query, parms) = set.select_query(false);
+        assert!(query.contains("WHERE ((id > $1) AND settings->>$2 = $3) AND settings->>$4 = $5"));
+        assert_eq!(parms.len(), 5);
+
+        let (query, parms) = posts().filter_json_text(settings, "theme", "dark").select_query(false);
+        assert!(query.contains("WHERE settings->>$1 = $2"));
+        assert_eq!(parms.len(), 2);
+
+        let (query, _) = posts().filter_json_contains(settings, serde_json::json!({ "dark": true })).select_query(false);
+        assert!(query.contains("WHERE settings @> $1"));
+    }
+
+    #[test]
+    fn self_join_test() {
+        let mut join = posts().alias("p")
+            .left_join(posts().alias("parent"), "p.parent_id = parent.id")
+            .order_by("p.id", Ordering::ASC);
+        let (query, _) = join.select_query(false);
+        assert!(query.starts_with("SELECT p.id, p.title, parent.id, parent.title, parent IS NULL AS \"parent.\" FROM posts p LEFT JOIN posts parent ON p.parent_id = parent.id"));
+    }
+
+    #[tokio::test]
+    async fn paginate_test() {
+        let mut set = posts()
+            .filter("title <> $1", crate::parms!["".to_string()])
+            .group_by(TITLE)
+            .having("COUNT(*) > $2", crate::parms![1i64])
+            .take(10)
+            .skip(20);
+        let (query, count_query, parms) = set.paginate_queries();
+        assert!(query.starts_with("SELECT id, title, COUNT(*) OVER() AS __dawnorm_total FROM posts WHERE title <> $1 GROUP BY title HAVING COUNT(*) > $2"));
+        assert!(query.contains("LIMIT 10 OFFSET 20"));
+        assert_eq!(count_query, "SELECT COUNT(*) FROM (SELECT 1 FROM posts WHERE title <> $1 GROUP BY title HAVING COUNT(*) > $2) __dawnorm_count;");
+        assert_eq!(parms.len(), 2);
+
+        assert!(matches!(posts().paginate(usize::MAX, 2).await, Err(Error::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn aggregate_test() {
+        let (query, parms) = posts().filter("id > $1", crate::parms![1]).aggregate_query("COUNT(*)").unwrap();
+        assert_eq!(query, "SELECT COUNT(*) FROM posts WHERE id > $1;");
+        assert_eq!(parms.len(), 1);
+
+        let mut grouped = posts().group_by(TITLE).having("COUNT(*) > $1", crate::parms![1i64]);
+        let (query, parms) = grouped.aggregate_query("COUNT(*)").unwrap();
+        assert_eq!(query, "SELECT COUNT(*) FROM (SELECT 1 FROM posts  GROUP BY title HAVING COUNT(*) > $1) __dawnorm_count;");
+        assert_eq!(parms.len(), 1);
+
+        let mut grouped = posts().group_by(TITLE);
+        assert!(matches!(grouped.aggregate_query("MAX(id)"), Err(Error::InvalidQuery(_))));
+    }
+
+    #[test]
+    fn identity_map_test() {
+        let map = IdentityMap::new();
+        let authors = || DbSet::<Author, NoConnection>::new(NoConnection, "authors".into()).identity_map(&map);
+        let author = Author { id: 1, name: "angus".into() };
+
+        authors().remember(std::slice::from_ref(&author));
+        assert_eq!(authors().filter_pk(crate::parms![1]).cached().map(|x| x.name), Some("angus".into()));
+        assert!(authors().filter_pk(crate::parms![1]).skip(1).cached().is_none());
+        assert!(authors().filter_pk(crate::parms![1]).take(0).cached().is_none());
+        assert!(authors().filter_pk(crate::parms![1]).alias("a").cached().is_none());
+
+        let mut in_transaction = authors();
+        in_transaction.evict_only = true;
+        in_transaction.remember(std::slice::from_ref(&author));
+        assert!(map.is_empty());
+
+        authors().remember(std::slice::from_ref(&author));
+        let forget = authors().forget_later(&author).unwrap();
+        assert_eq!(map.len(), 1);
+        forget();
+        assert!(map.is_empty());
+    }
+
+    #[tokio::test]
+    async fn restore_test() {
+        let comments = DbSet::<Comment, NoConnection>::new(NoConnection, "comments".into());
+        assert!(matches!(comments.restore().await, Err(Error::InvalidQuery(_))));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{fmt::Display, marker::PhantomData};
+
+use tokio_postgres::{Row, types::FromSql};
+
+use crate::Error;
+
+pub struct Field<T> {
+    name: &'static str,
+    phantom: PhantomData<fn() -> T>
+}
+
+impl<T> Field<T> {
+    pub const fn new(name: &'static str) -> Self {
+        Self { name, phantom: PhantomData }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+impl<T> Clone for Field<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for Field<T> {}
+
+impl<T> Display for Field<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.name)
+    }
+}
+
+impl<T> From<Field<T>> for &'static str {
+    fn from(field: Field<T>) -> Self {
+        field.name
+    }
+}
+
+impl<T> Field<T> {
+    pub fn count(self) -> Expr<i64> {
+        Expr::new(format!("COUNT({})", self.name))
+    }
+
+    pub fn min(self) -> Expr<Option<T>> {
+        Expr::new(format!("MIN({})", self.name))
+    }
+
+    pub fn max(self) -> Expr<Option<T>> {
+        Expr::new(format!("MAX({})", self.name))
+    }
+}
+
+impl<T: Numeric> Field<T> {
+    pub fn sum(self) -> Expr<Option<T::Sum>> {
+        Expr::new(format!("SUM({})::{}", self.name, T::SUM_TYPE))
+    }
+
+    pub fn avg(self) -> Expr<Option<f64>> {
+        Expr::new(format!("AVG({})::DOUBLE PRECISION", self.name))
+    }
+}
+
+pub struct Expr<T> {
+    sql: String,
+    phantom: PhantomData<fn() -> T>
+}
+
+impl<T> Expr<T> {
+    pub fn new<S: Into<String>>(sql: S) -> Self {
+        Self { sql: sql.into(), phantom: PhantomData }
+    }
+
+    pub fn sql(&self) -> &str {
+        &self.sql
+    }
+}
+
+impl<T> Display for Expr<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.sql)
+    }
+}
+
+pub fn count() -> Expr<i64> {
+    Expr::new("COUNT(*)")
+}
+
+pub trait Selectable {
+    type Value: for<'a> FromSql<'a>;
+    fn sql(&self) -> String;
+}
+
+impl<T: for<'a> FromSql<'a>> Selectable for Field<T> {
+    type Value = T;
+
+    fn sql(&self) -> String {
+        self.name.into()
+    }
+}
+
+impl<T: for<'a> FromSql<'a>> Selectable for Expr<T> {
+    type Value = T;
+
+    fn sql(&self) -> String {
+        self.sql.clone()
+    }
+}
+
+pub trait Numeric: for<'a> FromSql<'a> {
+    type Sum: for<'a> FromSql<'a>;
+    const SUM_TYPE: &'static str;
+}
+
+impl Numeric for i16 {
+    type Sum = i64;
+    const SUM_TYPE: &'static str = "BIGINT";
+}
+
+impl Numeric for i32 {
+    type Sum = i64;
+    const SUM_TYPE: &'static str = "BIGINT";
+}
+
+impl Numeric for i64 {
+    type Sum = i64;
+    const SUM_TYPE: &'static str = "BIGINT";
+}
+
+impl Numeric for f32 {
+    type Sum = f32;
+    const SUM_TYPE: &'static str = "REAL";
+}
+
+impl Numeric for f64 {
+    type Sum = f64;
+    const SUM_TYPE: &'static str = "DOUBLE PRECISION";
+}
+
+impl<V: Numeric> Numeric for Option<V> {
+    type Sum = V::Sum;
+    const SUM_TYPE: &'static str = V::SUM_TYPE;
+}
+
+pub trait FieldTuple {
+    type Output;
+    fn sql_fields(&self) -> String;
+    fn from_row(row: &Row) -> Result<Self::Output, Error>;
+}
+
+macro_rules! impl_field_tuple {
+    ( $( $t:ident : $idx:tt ),+ ) => {
+        impl<$( $t: Selectable ),+> FieldTuple for ($( $t, )+) {
+            type Output = ($( $t::Value, )+);
+
+            fn sql_fields(&self) -> String {
+                [$( self.$idx.sql() ),+].join(", ")
+            }
+
+            fn from_row(row: &Row) -> Result<Self::Output, Error> {
+                Ok(($( row.try_get::<usize, $t::Value>($idx)?, )+))
+            }
+        }
+    };
+}
+
+impl_field_tuple!(A: 0);
+impl_field_tuple!(A: 0, B: 1);
+impl_field_tuple!(A: 0, B: 1, C: 2);
+impl_field_tuple!(A: 0, B: 1, C: 2, D: 3);
+impl_field_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_field_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+impl_field_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6);
+impl_field_tuple!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7);
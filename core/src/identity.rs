@@ -0,0 +1,137 @@
+use std::{any::{Any, TypeId}, collections::HashMap, marker::PhantomData, sync::{Arc, Mutex}};
+
+use crate::Writable;
+
+type Entries = HashMap<(TypeId, String, String), Box<dyn Any + Send>>;
+
+// Entities cached by type, table and primary key, shared by the dbsets of a context
+#[derive(Clone, Default)]
+pub struct IdentityMap {
+    entries: Arc<Mutex<Entries>>
+}
+
+impl IdentityMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+}
+
+// the key parameters of the delete query, formatted like the filter_pk parameters
+pub(crate) fn identity_key<T: Writable>(obj: &T) -> String {
+    format!("{:?}", obj.get_delete_query("").1)
+}
+
+// Type erased so DbSet<T> can use the map without requiring T: Writable + Clone everywhere
+pub(crate) trait EntityCache<T>: Send + Sync {
+    fn get(&self, key: &str) -> Option<T>;
+    fn put(&self, obj: &T);
+    fn evict(&self, obj: &T);
+    // removes the entity of obj when called, independent of T
+    fn evict_later(&self, obj: &T) -> Box<dyn Fn() + Send + Sync>;
+    fn remove(&self, key: &str);
+    fn clear(&self);
+}
+
+pub(crate) struct TypedCache<T> {
+    map: IdentityMap,
+    table_name: String,
+    phantom: PhantomData<fn() -> T>
+}
+
+impl<T> TypedCache<T> {
+    pub(crate) fn new(map: &IdentityMap, table_name: &str) -> Self {
+        Self { map: map.clone(), table_name: table_name.to_string(), phantom: PhantomData }
+    }
+}
+
+impl<T: Clone + Send + 'static> TypedCache<T> {
+    fn entry_key(&self, key: &str) -> (TypeId, String, String) {
+        (TypeId::of::<T>(), self.table_name.clone(), key.to_string())
+    }
+
+    fn get_entry(&self, key: &str) -> Option<T> {
+        let entries = self.map.entries.lock().unwrap();
+        entries.get(&self.entry_key(key)).and_then(|x| x.downcast_ref::<T>()).cloned()
+    }
+
+    fn insert_entry(&self, key: &str, obj: &T) {
+        let entry_key = self.entry_key(key);
+        self.map.entries.lock().unwrap().insert(entry_key, Box::new(obj.clone()));
+    }
+
+    fn remove_entry(&self, key: &str) {
+        let entry_key = self.entry_key(key);
+        self.map.entries.lock().unwrap().remove(&entry_key);
+    }
+
+    fn clear_entries(&self) {
+        let type_id = TypeId::of::<T>();
+        self.map.entries.lock().unwrap().retain(|(x, table_name, _), _| *x != type_id || *table_name != self.table_name);
+    }
+}
+
+impl<T: Writable + Clone + Send + 'static> EntityCache<T> for TypedCache<T> {
+    fn get(&self, key: &str) -> Option<T> {
+        self.get_entry(key)
+    }
+
+    fn put(&self, obj: &T) {
+        self.insert_entry(&identity_key(obj), obj);
+    }
+
+    fn evict(&self, obj: &T) {
+        self.remove_entry(&identity_key(obj));
+    }
+
+    fn evict_later(&self, obj: &T) -> Box<dyn Fn() + Send + Sync> {
+        let entries = self.map.entries.clone();
+        let entry_key = self.entry_key(&identity_key(obj));
+        Box::new(move || {
+            entries.lock().unwrap().remove(&entry_key);
+        })
+    }
+
+    fn remove(&self, key: &str) {
+        self.remove_entry(key);
+    }
+
+    fn clear(&self) {
+        self.clear_entries();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IdentityMap, TypedCache};
+
+    #[test]
+    fn identity_map_test() {
+        let map = IdentityMap::new();
+        let posts = TypedCache::<String>::new(&map, "posts");
+        let pages = TypedCache::<String>::new(&map, "pages");
+
+        posts.insert_entry("[1]", &"post".to_string());
+        pages.insert_entry("[1]", &"page".to_string());
+        assert_eq!(posts.get_entry("[1]").as_deref(), Some("post"));
+        assert_eq!(TypedCache::<i32>::new(&map, "posts").get_entry("[1]"), None);
+
+        posts.clear_entries();
+        assert_eq!(posts.get_entry("[1]"), None);
+        assert_eq!(pages.get_entry("[1]").as_deref(), Some("page"));
+
+        pages.remove_entry("[1]");
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,68 @@
+use std::fmt::Debug;
+
+use bytes::BytesMut;
+use serde::{de::DeserializeOwned, Serialize};
+use tokio_postgres::types::{FromSql, IsNull, ToSql, Type};
+
+// Adapter for fields marked #[dawnorm(json)], stored in JSON or JSONB columns.
+// NULL reads as JSON null and null is written as NULL, so Option fields stay nullable
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize + Debug> ToSql for Json<T> {
+    fn to_sql(&self, ty: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn std::error::Error + Sync + Send>> {
+        match serde_json::to_value(&self.0)? {
+            serde_json::Value::Null => Ok(IsNull::Yes),
+            value => value.to_sql(ty, out)
+        }
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <serde_json::Value as ToSql>::accepts(ty)
+    }
+
+    tokio_postgres::types::to_sql_checked!();
+}
+
+impl<'a, T: DeserializeOwned> FromSql<'a> for Json<T> {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        let value = serde_json::Value::from_sql(ty, raw)?;
+        Ok(Json(serde_json::from_value(value)?))
+    }
+
+    fn from_sql_null(_: &Type) -> Result<Self, Box<dyn std::error::Error + Sync + Send>> {
+        Ok(Json(serde_json::from_value(serde_json::Value::Null)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        <serde_json::Value as FromSql>::accepts(ty)
+    }
+}
+
+// Values of JSON or JSONB columns, the fields filter_json_text and filter_json_contains take
+pub trait JsonColumn {}
+
+impl<T> JsonColumn for Json<T> {}
+
+impl JsonColumn for serde_json::Value {}
+
+impl<V: JsonColumn> JsonColumn for Option<V> {}
+
+#[cfg(test)]
+mod tests {
+    use super::Json;
+    use bytes::BytesMut;
+    use tokio_postgres::types::{FromSql, IsNull, ToSql, Type};
+
+    #[test]
+    fn json_test() {
+        let mut out = BytesMut::new();
+        assert!(matches!(Json(vec![1, 2]).to_sql(&Type::JSONB, &mut out).unwrap(), IsNull::No));
+        assert_eq!(Json::<Vec<i32>>::from_sql(&Type::JSONB, &out).unwrap(), Json(vec![1, 2]));
+
+        assert!(matches!(Json(None::<i32>).to_sql(&Type::JSONB, &mut BytesMut::new()).unwrap(), IsNull::Yes));
+        assert_eq!(Json::<Option<i32>>::from_sql_null(&Type::JSONB).unwrap(), Json(None));
+        assert!(Json::<i32>::from_sql_null(&Type::JSONB).is_err());
+        assert!(!<Json<i32> as ToSql>::accepts(&Type::TEXT));
+    }
+}
@@ -1,8 +1,36 @@
+// lets the derives, which generate dawnorm:: paths, be used by the tests of this crate
+#[cfg(test)]
+extern crate self as dawnorm;
+
 pub mod context;
 pub mod migration;
+pub mod scaffold;
+mod connection;
+mod field;
+mod identity;
+#[cfg(feature = "serde")]
+mod json;
+mod page;
+mod relation;
+mod tracked;
+mod tracker;
+
+// the buffer ToSql writes to, for generated ToSql impls
+pub use bytes::BytesMut;
+pub use connection::{Connection, Executor};
+pub use field::{count, Expr, Field, FieldTuple, Numeric, Selectable};
+pub use identity::IdentityMap;
+#[cfg(feature = "serde")]
+pub use json::{Json, JsonColumn};
+pub use page::{Cursor, Page, Paged};
+pub use relation::{Relation, RelationFuture, RelationKey};
+pub use tracked::Tracked;
+pub use tracker::ChangeTracker;
+
+use std::borrow::Cow;
 
 use thiserror::Error;
-use tokio_postgres::Row;
+use tokio_postgres::{Row, types::FromSql};
 
 
 #[derive(Error, Debug)]
@@ -10,7 +38,9 @@ pub enum Error {
     #[error("Postgres Error: {0}")]
     TokioPostgres(tokio_postgres::Error),
     #[error("No Result Found")]
-    NoResult
+    NoResult,
+    #[error("Invalid Query: {0}")]
+    InvalidQuery(String)
 }
 
 impl From<tokio_postgres::Error> for Error {
@@ -26,12 +56,69 @@ pub struct EntityFieldDefinition {
 }
 
 
-pub trait Entity: Sized {
-    fn from_row(row: Row) -> Result<Self, Error>;
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self, Error> {
+        Self::from_row_prefixed(row, "")
+    }
+
+    fn from_row_prefixed(row: &Row, prefix: &str) -> Result<Self, Error>;
+}
+
+pub fn prefixed<'a>(prefix: &str, column: &'a str) -> Cow<'a, str> {
+    if prefix.is_empty() {
+        Cow::Borrowed(column)
+    } else {
+        Cow::Owned(format!("{}{}", prefix, column))
+    }
+}
+
+pub fn get_or_default<T: for<'a> FromSql<'a> + Default>(row: &Row, column: &str) -> Result<T, Error> {
+    match row.columns().iter().position(|x| x.name() == column) {
+        Some(idx) => Ok(row.try_get::<usize, Option<T>>(idx)?.unwrap_or_default()),
+        None => Ok(T::default())
+    }
+}
+
+// Query side of an entity, read_only entities mapped to views only implement this
+pub trait Entity: FromRow {
     fn sql_fields() -> &'static str;
+    fn sql_field_names() -> &'static [&'static str];
+    fn sql_soft_delete_field() -> Option<&'static str> {
+        None
+    }
     fn sql_table_fields(table_name: &str) -> String;
+}
+
+// Write side of an entity with a key
+pub trait Writable: Entity {
+    // the key field type, or a tuple of them for composite keys
+    type Key: Send;
+    // tables of belongs_to and has_many relations, ChangeTracker writes
+    // parents before their children and removes children first
+    fn sql_parent_tables() -> &'static [&'static str] {
+        &[]
+    }
+    fn sql_child_tables() -> &'static [&'static str] {
+        &[]
+    }
+    fn key_parms(key: Self::Key) -> Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>;
+    fn sql_key_filter(keys: Vec<Self::Key>) -> (String, Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>);
     fn sql_key_constrint() -> &'static str;
     fn get_insert_query(self, table_name: &str) -> (String, Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>);
     fn get_update_query(self, table_name: &str) -> (String, Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>);
     fn get_delete_query(&self, table_name: &str) -> (String, Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>);
 }
+
+// Implemented when every updatable field is PartialEq, the query only sets changed
+// columns (key columns are never changed) and Err gives self back when nothing changed
+pub trait Tracking: Writable {
+    fn get_tracked_update_query(self, original: &Self, table_name: &str) -> Result<(String, Vec<Box<dyn tokio_postgres::types::ToSql + Send + Sync>>), Self>;
+}
+
+pub fn not_deleted<T: Entity>(table_name: &str) -> Option<String> {
+    T::sql_soft_delete_field().map(|x| format!("{}.{} IS NULL", table_name, x))
+}
+
+pub trait Projection: FromRow {
+    fn sql_fields() -> &'static str;
+}
@@ -13,6 +13,12 @@ pub struct Migrator {
     pub migrations: Vec<Migration>
 }
 
+impl Default for Migrator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Migrator {
     pub fn new() -> Self {
         Self { migrations: Vec::new() }
@@ -0,0 +1,71 @@
+use std::fmt::Display;
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+
+use crate::Error;
+
+// url safe base64 of the json object with the order_by values of the last item
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cursor(String);
+
+impl Cursor {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub(crate) fn encode(values: &str) -> Self {
+        Self(URL_SAFE_NO_PAD.encode(values))
+    }
+
+    pub(crate) fn decode(&self) -> Result<String, Error> {
+        URL_SAFE_NO_PAD.decode(&self.0).ok()
+            .and_then(|x| String::from_utf8(x).ok())
+            .ok_or_else(|| Error::InvalidQuery("invalid cursor".into()))
+    }
+}
+
+impl Display for Cursor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for Cursor {
+    fn from(token: String) -> Self {
+        Self(token)
+    }
+}
+
+impl From<Cursor> for String {
+    fn from(cursor: Cursor) -> Self {
+        cursor.0
+    }
+}
+
+#[derive(Debug)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub next: Option<Cursor>,
+}
+
+#[derive(Debug)]
+pub struct Paged<T> {
+    pub items: Vec<T>,
+    pub total: u64,
+    pub page: usize,
+    pub page_size: usize,
+    pub total_pages: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Cursor;
+
+    #[test]
+    fn cursor_test() {
+        let cursor = Cursor::encode(r#"{"id" : 3}"#);
+        assert!(!cursor.as_str().contains('{'));
+        assert_eq!(cursor.decode().unwrap(), r#"{"id" : 3}"#);
+        assert!(Cursor::from("not a cursor!".to_string()).decode().is_err());
+    }
+}
@@ -0,0 +1,50 @@
+use std::{future::Future, pin::Pin};
+
+use tokio_postgres::types::ToSql;
+
+use crate::{Connection, Error};
+
+pub type RelationFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+
+pub struct Relation<T> {
+    loader: for<'a> fn(Connection<'a>, &'a mut [T]) -> RelationFuture<'a>
+}
+
+impl<T> Relation<T> {
+    pub fn new(loader: for<'a> fn(Connection<'a>, &'a mut [T]) -> RelationFuture<'a>) -> Self {
+        Self { loader }
+    }
+
+    pub async fn load(&self, client: Connection<'_>, parents: &mut [T]) -> Result<(), Error> {
+        (self.loader)(client, parents).await
+    }
+}
+
+pub trait RelationKey {
+    type Key: PartialEq + ToSql + Send + Sync + 'static;
+    fn relation_key(&self) -> Option<Self::Key>;
+}
+
+macro_rules! impl_relation_key {
+    ( $( $t:ty ),+ ) => {
+        $(
+            impl RelationKey for $t {
+                type Key = $t;
+
+                fn relation_key(&self) -> Option<Self::Key> {
+                    Some(self.clone())
+                }
+            }
+        )+
+    };
+}
+
+impl_relation_key!(i16, i32, i64, u32, String);
+
+impl<T: RelationKey> RelationKey for Option<T> {
+    type Key = T::Key;
+
+    fn relation_key(&self) -> Option<Self::Key> {
+        self.as_ref().and_then(|x| x.relation_key())
+    }
+}
@@ -0,0 +1,313 @@
+use tokio_postgres::Client;
+
+use crate::Error;
+
+pub struct ScaffoldColumn {
+    pub name: String,
+    pub psql_type: String,
+    pub optional: bool,
+    pub key: bool,
+    // filled in by the database on insert
+    pub generated: bool,
+    // GENERATED ALWAYS identity or computed columns, never written
+    pub always_generated: bool,
+}
+
+pub struct ScaffoldForeignKey {
+    pub column: String,
+    pub foreign_table: String,
+    pub foreign_column: String,
+}
+
+pub struct ScaffoldTable {
+    pub name: String,
+    pub columns: Vec<ScaffoldColumn>,
+    pub foreign_keys: Vec<ScaffoldForeignKey>,
+}
+
+pub struct Scaffolder {
+    pub schema: String,
+    pub context_name: String,
+}
+
+impl Default for Scaffolder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scaffolder {
+    pub fn new() -> Self {
+        Self { schema: "public".into(), context_name: "DbContext".into() }
+    }
+
+    pub fn schema(mut self, schema: &str) -> Self {
+        self.schema = schema.into();
+        self
+    }
+
+    pub fn context_name(mut self, context_name: &str) -> Self {
+        self.context_name = context_name.into();
+        self
+    }
+
+    pub async fn introspect(&self, client: &Client) -> Result<Vec<ScaffoldTable>, Error> {
+        let table_rows = client
+            .query(r#"SELECT table_name::text FROM information_schema.tables
+                WHERE table_schema = $1 AND
+                table_type = 'BASE TABLE' AND
+                table_name != '__dawnorm_schema_migrations'
+                ORDER BY table_name;"#, &[&self.schema])
+            .await?;
+
+        let mut tables = Vec::new();
+        for table_row in table_rows {
+            let table_name: String = table_row.try_get(0)?;
+
+            let keys = client
+                .query(r#"SELECT kcu.column_name::text FROM information_schema.table_constraints tc
+                    JOIN information_schema.key_column_usage kcu
+                        ON tc.constraint_name = kcu.constraint_name AND tc.table_schema = kcu.table_schema
+                    WHERE tc.constraint_type = 'PRIMARY KEY' AND tc.table_schema = $1 AND tc.table_name = $2
+                    ORDER BY kcu.ordinal_position;"#, &[&self.schema, &table_name])
+                .await?
+                .into_iter()
+                .map(|x| x.try_get::<usize, String>(0))
+                .collect::<Result<Vec<String>, tokio_postgres::Error>>()?;
+
+            let columns = client
+                .query(r#"SELECT column_name::text, udt_name::text, is_nullable::text, column_default::text, is_identity::text,
+                        identity_generation::text, is_generated::text
+                    FROM information_schema.columns
+                    WHERE table_schema = $1 AND table_name = $2
+                    ORDER BY ordinal_position;"#, &[&self.schema, &table_name])
+                .await?
+                .into_iter()
+                .map(|x| {
+                    let name: String = x.try_get(0)?;
+                    let default: Option<String> = x.try_get(3)?;
+                    let always_generated = x.try_get::<usize, Option<String>>(5)?.as_deref() == Some("ALWAYS") ||
+                        x.try_get::<usize, String>(6)? == "ALWAYS";
+                    Ok(ScaffoldColumn {
+                        key: keys.contains(&name),
+                        name,
+                        psql_type: x.try_get(1)?,
+                        optional: x.try_get::<usize, String>(2)? == "YES",
+                        generated: always_generated || x.try_get::<usize, String>(4)? == "YES" ||
+                            default.map(|d| d.starts_with("nextval(")).unwrap_or(false),
+                        always_generated,
+                    })
+                })
+                .collect::<Result<Vec<ScaffoldColumn>, tokio_postgres::Error>>()?;
+
+            let foreign_keys = client
+                .query(r#"SELECT kcu.column_name::text, ukcu.table_name::text, ukcu.column_name::text
+                    FROM information_schema.referential_constraints rc
+                    JOIN information_schema.key_column_usage kcu
+                        ON rc.constraint_name = kcu.constraint_name AND rc.constraint_schema = kcu.constraint_schema
+                    JOIN information_schema.key_column_usage ukcu
+                        ON rc.unique_constraint_name = ukcu.constraint_name AND rc.unique_constraint_schema = ukcu.constraint_schema
+                        AND kcu.position_in_unique_constraint = ukcu.ordinal_position
+                    WHERE kcu.table_schema = $1 AND kcu.table_name = $2
+                    ORDER BY kcu.constraint_name, kcu.ordinal_position;"#, &[&self.schema, &table_name])
+                .await?
+                .into_iter()
+                .map(|x| Ok(ScaffoldForeignKey {
+                    column: x.try_get(0)?,
+                    foreign_table: x.try_get(1)?,
+                    foreign_column: x.try_get(2)?,
+                }))
+                .collect::<Result<Vec<ScaffoldForeignKey>, tokio_postgres::Error>>()?;
+
+            tables.push(ScaffoldTable { name: table_name, columns, foreign_keys });
+        }
+
+        Ok(tables)
+    }
+
+    pub fn generate(&self, tables: &[ScaffoldTable]) -> String {
+        let mut out = String::new();
+        let mut requirements = tables.iter()
+            .flat_map(|x| x.columns.iter())
+            .filter_map(|x| rust_type(&x.psql_type).and_then(|x| x.1))
+            .collect::<Vec<&str>>();
+        requirements.sort();
+        requirements.dedup();
+        for requirement in requirements {
+            out.push_str(&format!("// requires {}\n", requirement));
+        }
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        out.push_str("use std::sync::Arc;\n\nuse tokio_postgres::Client;\n");
+
+        for table in tables {
+            out.push_str("\n#[derive(dawnorm_codegen::Entity, Debug)]\n");
+            // without a primary key the entity can only be queried
+            if !table.columns.iter().any(|x| x.key) {
+                out.push_str("#[dawnorm(read_only)]\n");
+            }
+            out.push_str(&format!("pub struct {} {{\n", struct_name(&table.name)));
+            for column in &table.columns {
+                let ty = match rust_type(&column.psql_type) {
+                    Some((ty, _)) => ty,
+                    None => {
+                        out.push_str(&format!("    // skipped {}, {} has no rust type\n", column.name, column.psql_type));
+                        continue;
+                    }
+                };
+                if let Some(fk) = table.foreign_keys.iter().find(|x| x.column == column.name) {
+                    out.push_str(&format!("    /// references {}({})\n", fk.foreign_table, fk.foreign_column));
+                }
+                let attr = match (column.key, column.generated, column.always_generated) {
+                    (true, true, _) => Some("key_noinsert_noupdate"),
+                    (true, false, _) => Some("key"),
+                    (false, _, true) => Some("noinsert_noupdate"),
+                    (false, true, false) => Some("noinsert"),
+                    (false, false, false) => None,
+                };
+                if let Some(attr) = attr {
+                    out.push_str(&format!("    #[{}]\n", attr));
+                }
+                let field = field_name(&column.name);
+                if field != column.name {
+                    out.push_str(&format!("    #[dawnorm(rename = \"{}\")]\n", column.name));
+                }
+                let ty = if column.optional { format!("Option<{}>", ty) } else { ty };
+                out.push_str(&format!("    pub {}: {},\n", field, ty));
+            }
+            out.push_str("}\n");
+        }
+
+        out.push_str(&format!("\npub struct {} {{\n    pub client: Arc<Client>,\n}}\n\nimpl {} {{\n", self.context_name, self.context_name));
+        for table in tables {
+            let name = field_name(&table.name);
+            // the table is only passed when the function name can't be used as it
+            if self.schema != "public" {
+                out.push_str(&format!("    dawnorm::dbset!({}, {}, \"{}.{}\");\n", name, struct_name(&table.name), self.schema, table.name));
+            } else if name != table.name {
+                out.push_str(&format!("    dawnorm::dbset!({}, {}, \"{}\");\n", name, struct_name(&table.name), table.name));
+            } else {
+                out.push_str(&format!("    dawnorm::dbset!({}, {});\n", name, struct_name(&table.name)));
+            }
+        }
+        out.push_str("}\n");
+        out
+    }
+}
+
+fn struct_name(table_name: &str) -> String {
+    table_name.split('_')
+        .filter(|x| !x.is_empty())
+        .map(|x| {
+            let mut chars = x.chars();
+            match chars.next() {
+                Some(c) => c.to_uppercase().chain(chars).collect::<String>(),
+                None => String::new(),
+            }
+        })
+        .collect()
+}
+
+// keywords become raw identifiers, the ones that can't be raw get a trailing _
+fn field_name(column: &str) -> String {
+    const KEYWORDS: &[&str] = &[
+        "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn",
+        "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop",
+        "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref", "return", "static",
+        "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use", "virtual",
+        "where", "while", "yield",
+    ];
+
+    match column {
+        "self" | "super" | "crate" | "_" => format!("{}_", column),
+        x if KEYWORDS.contains(&x) => format!("r#{}", x),
+        x => x.into(),
+    }
+}
+
+// the rust type of a column and what it requires of the crate using the entities,
+// None when the type has no mapping and the column is skipped
+fn rust_type(udt_name: &str) -> Option<(String, Option<&'static str>)> {
+    if let Some(element) = udt_name.strip_prefix('_') {
+        return rust_type(element).map(|(ty, requirement)| (format!("Vec<{}>", ty), requirement));
+    }
+
+    let requirement = match udt_name {
+        "uuid" => Some("the with-uuid-1 feature of tokio-postgres"),
+        "date" | "time" => Some("the with-chrono-0_4 feature of tokio-postgres"),
+        "json" | "jsonb" => Some("the serde feature of dawnorm"),
+        "numeric" => Some("rust_decimal with its db-tokio-postgres feature"),
+        _ => None,
+    };
+
+    let ty = match udt_name {
+        "bool" => "bool",
+        "char" => "i8",
+        "int2" => "i16",
+        "int4" => "i32",
+        "oid" => "u32",
+        "int8" => "i64",
+        "float4" => "f32",
+        "float8" => "f64",
+        "bytea" => "Vec<u8>",
+        "hstore" => "std::collections::HashMap<String, Option<String>>",
+        "timestamp" | "timestamptz" => "std::time::SystemTime",
+        "inet" => "std::net::IpAddr",
+        "text" | "varchar" | "bpchar" | "name" | "citext" => "String",
+        "uuid" => "uuid::Uuid",
+        "date" => "chrono::NaiveDate",
+        "time" => "chrono::NaiveTime",
+        "json" | "jsonb" => "serde_json::Value",
+        "numeric" => "rust_decimal::Decimal",
+        _ => return None,
+    };
+    Some((ty.into(), requirement))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::scaffold::*;
+
+    #[test]
+    pub fn scaffold_generate_test() {
+        let tables = vec![ScaffoldTable {
+            name: "blog_posts".into(),
+            columns: vec![
+                ScaffoldColumn { name: "id".into(), psql_type: "int4".into(), optional: false, key: true, generated: true, always_generated: false },
+                ScaffoldColumn { name: "author_id".into(), psql_type: "int8".into(), optional: false, key: false, generated: false, always_generated: false },
+                ScaffoldColumn { name: "tags".into(), psql_type: "_text".into(), optional: true, key: false, generated: false, always_generated: false },
+                ScaffoldColumn { name: "type".into(), psql_type: "varchar".into(), optional: false, key: false, generated: false, always_generated: false },
+                ScaffoldColumn { name: "published_on".into(), psql_type: "date".into(), optional: true, key: false, generated: false, always_generated: false },
+                ScaffoldColumn { name: "slug".into(), psql_type: "text".into(), optional: false, key: false, generated: true, always_generated: true },
+                ScaffoldColumn { name: "price".into(), psql_type: "money".into(), optional: false, key: false, generated: false, always_generated: false },
+            ],
+            foreign_keys: vec![ScaffoldForeignKey { column: "author_id".into(), foreign_table: "authors".into(), foreign_column: "id".into() }],
+        }, ScaffoldTable {
+            name: "audit_log".into(),
+            columns: vec![
+                ScaffoldColumn { name: "message".into(), psql_type: "text".into(), optional: false, key: false, generated: false, always_generated: false },
+            ],
+            foreign_keys: vec![],
+        }];
+
+        let out = Scaffolder::new().generate(&tables);
+
+        assert!(out.contains("pub struct BlogPosts {"));
+        assert!(out.contains("    #[key_noinsert_noupdate]\n    pub id: i32,"));
+        assert!(out.contains("    /// references authors(id)\n    pub author_id: i64,"));
+        assert!(out.contains("    pub tags: Option<Vec<String>>,"));
+        assert!(out.contains("    dawnorm::dbset!(blog_posts, BlogPosts);"));
+        assert!(out.contains("#[dawnorm(read_only)]\npub struct AuditLog {"));
+        assert!(!out.contains("#[dawnorm(read_only)]\npub struct BlogPosts {"));
+        assert!(out.contains("    #[dawnorm(rename = \"type\")]\n    pub r#type: String,"));
+        assert!(out.contains("    pub published_on: Option<chrono::NaiveDate>,"));
+        assert!(out.starts_with("// requires the with-chrono-0_4 feature of tokio-postgres\n\n"));
+        assert!(out.contains("    // skipped price, money has no rust type\n"));
+        assert!(!out.contains("pub price"));
+        assert!(out.contains("    #[noinsert_noupdate]\n    pub slug: String,"));
+
+        let out = Scaffolder::new().schema("shop").generate(&tables);
+        assert!(out.contains("    dawnorm::dbset!(blog_posts, BlogPosts, \"shop.blog_posts\");"));
+    }
+}
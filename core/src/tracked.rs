@@ -0,0 +1,51 @@
+use std::ops::{Deref, DerefMut};
+
+use tokio_postgres::{Row, types::ToSql};
+
+use crate::{Error, FromRow, Tracking};
+
+// Entity loaded together with a snapshot of its original values,
+// updates only write the columns that differ from the snapshot
+#[derive(Debug)]
+pub struct Tracked<T> {
+    entity: T,
+    original: T,
+}
+
+impl<T: FromRow> Tracked<T> {
+    pub fn from_row(row: &Row) -> Result<Self, Error> {
+        // the snapshot is read from the same row, so T does not need to be Clone
+        Ok(Self { entity: T::from_row(row)?, original: T::from_row(row)? })
+    }
+}
+
+impl<T> Tracked<T> {
+    pub fn original(&self) -> &T {
+        &self.original
+    }
+
+    pub fn into_inner(self) -> T {
+        self.entity
+    }
+}
+
+impl<T: Tracking> Tracked<T> {
+    // gives the entity back when none of its columns changed
+    pub fn get_update_query(self, table_name: &str) -> Result<(String, Vec<Box<dyn ToSql + Send + Sync>>), T> {
+        self.entity.get_tracked_update_query(&self.original, table_name)
+    }
+}
+
+impl<T> Deref for Tracked<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.entity
+    }
+}
+
+impl<T> DerefMut for Tracked<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.entity
+    }
+}
@@ -0,0 +1,246 @@
+use std::sync::Mutex;
+
+use tokio_postgres::{Client, types::ToSql};
+
+use crate::{context::DbSet, Error, Executor, Tracked, Tracking, Writable};
+
+#[derive(PartialEq)]
+enum ChangeKind {
+    Added,
+    Modified,
+    Removed
+}
+
+struct Change {
+    kind: ChangeKind,
+    table: String,
+    parents: &'static [&'static str],
+    children: &'static [&'static str],
+    query: String,
+    parms: Vec<Box<dyn ToSql + Send + Sync>>,
+    // evicts the entity from the identity map once the change is committed
+    forget: Option<Box<dyn Fn() + Send + Sync>>
+}
+
+// Records changes on a context and writes them with save_changes in one transaction, in
+// dependency order: inserts and updates of parents before their children, then removals of
+// children before their parents. Parents and children are the tables named by belongs_to
+// and has_many, changes of the same table or of tables in a cycle keep the order they were
+// recorded in, removals the reverse of it
+#[derive(Default)]
+pub struct ChangeTracker {
+    changes: Mutex<Vec<Change>>
+}
+
+impl ChangeTracker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add<T: Writable, E: Executor>(&self, set: DbSet<T, E>, obj: T) {
+        let change = Change::new::<T>(ChangeKind::Added, set.table_name(), obj.get_insert_query(set.table_name()), None);
+        self.push(change);
+    }
+
+    // entities without changes are not recorded
+    pub fn update<T: Tracking, E: Executor>(&self, set: DbSet<T, E>, obj: Tracked<T>) {
+        // the identity map reloads the entity once the change is saved
+        set.forget(&obj);
+        let forget = set.forget_later(&obj);
+        if let Ok(query) = obj.get_update_query(set.table_name()) {
+            self.push(Change::new::<T>(ChangeKind::Modified, set.table_name(), query, forget));
+        }
+    }
+
+    pub fn remove<T: Writable, E: Executor>(&self, set: DbSet<T, E>, obj: &T) {
+        set.forget(obj);
+        let change = Change::new::<T>(ChangeKind::Removed, set.table_name(), obj.get_delete_query(set.table_name()), set.forget_later(obj));
+        self.push(change);
+    }
+
+    pub fn has_changes(&self) -> bool {
+        !self.changes.lock().unwrap().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.changes.lock().unwrap().clear();
+    }
+
+    // returns the number of affected rows. The transaction is opened on the client given
+    // exclusively, so queries of other tasks can't end up in it. When anything fails,
+    // including COMMIT, or the future is dropped, the transaction is rolled back
+    // and the changes stay recorded
+    pub async fn save_changes(&self, client: &mut Client) -> Result<u64, Error> {
+        let pending = self.take_pending();
+        if pending.changes.is_empty() {
+            return Ok(0);
+        }
+
+        let affected = Self::execute(client, &pending.ordered()).await?;
+        pending.committed();
+        Ok(affected)
+    }
+
+    fn take_pending(&self) -> Pending<'_> {
+        Pending { tracker: self, changes: std::mem::take(&mut *self.changes.lock().unwrap()) }
+    }
+
+    async fn execute(client: &mut Client, changes: &[&Change]) -> Result<u64, Error> {
+        let transaction = client.transaction().await?;
+        let mut affected = 0;
+        for change in changes {
+            let ps : Vec<&(dyn ToSql + Sync)> = change.parms.iter().map(|x| x.as_ref() as &(dyn ToSql + Sync)).collect();
+            affected += transaction.execute(&change.query, ps.as_slice()).await?;
+        }
+        transaction.commit().await?;
+        Ok(affected)
+    }
+
+    fn push(&self, change: Change) {
+        self.changes.lock().unwrap().push(change);
+    }
+}
+
+impl Change {
+    fn new<T: Writable>(
+        kind: ChangeKind,
+        table: &str,
+        (query, parms): (String, Vec<Box<dyn ToSql + Send + Sync>>),
+        forget: Option<Box<dyn Fn() + Send + Sync>>
+    ) -> Self {
+        Self { kind, table: table.into(), parents: T::sql_parent_tables(), children: T::sql_child_tables(), query, parms, forget }
+    }
+
+    fn depends_on(&self, table: &str) -> bool {
+        self.table != table && self.parents.contains(&table)
+    }
+
+    fn parent_of(&self, table: &str) -> bool {
+        self.table != table && self.children.contains(&table)
+    }
+}
+
+// Changes taken out for save_changes, put back in front of the ones recorded
+// since unless they were committed
+struct Pending<'a> {
+    tracker: &'a ChangeTracker,
+    changes: Vec<Change>
+}
+
+impl Pending<'_> {
+    fn ordered(&self) -> Vec<&Change> {
+        let tables = table_order(&self.changes);
+        let rank = |x: &&Change| tables.iter().position(|table| *table == x.table).unwrap();
+
+        let mut writes = self.changes.iter().filter(|x| x.kind != ChangeKind::Removed).collect::<Vec<&Change>>();
+        writes.sort_by_key(rank);
+        let mut removals = self.changes.iter().rev().filter(|x| x.kind == ChangeKind::Removed).collect::<Vec<&Change>>();
+        removals.sort_by_key(|x| std::cmp::Reverse(rank(x)));
+        writes.into_iter().chain(removals).collect()
+    }
+
+    // entities read again before the commit may be in the identity map with their old rows
+    fn committed(mut self) {
+        self.changes.drain(..).filter_map(|x| x.forget).for_each(|forget| forget());
+    }
+}
+
+// the tables of the changes, parents first. When no table is left without a parent
+// among the remaining ones they form a cycle and the first recorded is taken
+fn table_order(changes: &[Change]) -> Vec<&str> {
+    let mut tables: Vec<&str> = Vec::new();
+    for change in changes {
+        if !tables.contains(&change.table.as_str()) {
+            tables.push(&change.table);
+        }
+    }
+
+    let depends = |table: &str, parent: &str| changes.iter().any(|x|
+        (x.table == table && x.depends_on(parent)) || (x.table == parent && x.parent_of(table))
+    );
+
+    let mut ordered = Vec::new();
+    while !tables.is_empty() {
+        let next = tables.iter().position(|x| !tables.iter().any(|parent| depends(x, parent))).unwrap_or(0);
+        ordered.push(tables.remove(next));
+    }
+    ordered
+}
+
+impl Drop for Pending<'_> {
+    fn drop(&mut self) {
+        if self.changes.is_empty() {
+            return;
+        }
+        let mut recorded = self.tracker.changes.lock().unwrap();
+        let recorded_since = std::mem::replace(&mut *recorded, std::mem::take(&mut self.changes));
+        recorded.extend(recorded_since);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+
+    use super::{Change, ChangeKind, ChangeTracker};
+
+    fn change(kind: ChangeKind, query: &str, table: &str, parents: &'static [&'static str], children: &'static [&'static str]) -> Change {
+        Change { kind, table: table.into(), parents, children, query: query.into(), parms: Vec::new(), forget: None }
+    }
+
+    fn queries(tracker: &ChangeTracker) -> Vec<String> {
+        tracker.changes.lock().unwrap().iter().map(|x| x.query.clone()).collect()
+    }
+
+    #[test]
+    fn order_test() {
+        let tracker = ChangeTracker::new();
+        tracker.push(change(ChangeKind::Added, "add comment", "comments", &["posts"], &[]));
+        tracker.push(change(ChangeKind::Removed, "remove author", "authors", &[], &[]));
+        tracker.push(change(ChangeKind::Added, "add post", "posts", &["authors"], &["comments"]));
+        tracker.push(change(ChangeKind::Removed, "remove comment", "comments", &["posts"], &[]));
+        tracker.push(change(ChangeKind::Added, "add author", "authors", &[], &[]));
+        tracker.push(change(ChangeKind::Modified, "update post", "posts", &["authors"], &["comments"]));
+        tracker.push(change(ChangeKind::Removed, "remove post", "posts", &["authors"], &["comments"]));
+
+        let pending = tracker.take_pending();
+        let ordered = pending.ordered().iter().map(|x| x.query.as_str()).collect::<Vec<&str>>();
+        assert_eq!(ordered, [
+            "add author", "add post", "update post", "add comment",
+            "remove comment", "remove post", "remove author"
+        ]);
+
+        // tables referencing each other or themselves keep the recorded order
+        let tracker = ChangeTracker::new();
+        tracker.push(change(ChangeKind::Added, "add reply", "posts", &["posts", "users"], &[]));
+        tracker.push(change(ChangeKind::Added, "add user", "users", &["posts"], &["posts"]));
+        tracker.push(change(ChangeKind::Added, "add post", "posts", &["posts", "users"], &[]));
+
+        let pending = tracker.take_pending();
+        let ordered = pending.ordered().iter().map(|x| x.query.as_str()).collect::<Vec<&str>>();
+        assert_eq!(ordered, ["add reply", "add post", "add user"]);
+    }
+
+    #[test]
+    fn pending_test() {
+        let forgotten = Arc::new(AtomicUsize::new(0));
+        let tracker = ChangeTracker::new();
+        let mut first = change(ChangeKind::Modified, "first", "posts", &[], &[]);
+        let counter = forgotten.clone();
+        first.forget = Some(Box::new(move || { counter.fetch_add(1, Ordering::SeqCst); }));
+        tracker.push(first);
+
+        // failed or dropped, the changes are put back in front of the ones recorded since
+        let pending = tracker.take_pending();
+        assert!(!tracker.has_changes());
+        tracker.push(change(ChangeKind::Added, "second", "posts", &[], &[]));
+        drop(pending);
+        assert_eq!(queries(&tracker), ["first", "second"]);
+        assert_eq!(forgotten.load(Ordering::SeqCst), 0);
+
+        // committed, they are gone and their entities evicted
+        tracker.take_pending().committed();
+        assert!(!tracker.has_changes());
+        assert_eq!(forgotten.load(Ordering::SeqCst), 1);
+    }
+}
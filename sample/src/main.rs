@@ -1,6 +1,6 @@
 mod migrations;
 
-use dawnorm::{context::*, parms};
+use dawnorm::{context::*, parms, ChangeTracker, IdentityMap};
 use std::sync::Arc;
 
 use tokio_postgres::{Client, NoTls};
@@ -11,19 +11,120 @@ pub struct Post {
     id: i32,
     title: String,
     body: Option<String>,
+    author_id: Option<i32>,
+    #[dawnorm(insert_default)]
+    views: Option<i32>,
+    state: PostState,
+    #[dawnorm(belongs_to = Author, table = "authors")]
+    author: Option<Author>,
+    #[dawnorm(has_many = Comment, fk = "post_id", table = "comments")]
+    comments: Vec<Comment>,
+}
+
+#[derive(dawnorm_codegen::PgEnum, Debug, Default, PartialEq)]
+pub enum PostState {
+    #[default]
+    Draft,
+    Published,
+    Archived,
+}
+
+#[derive(dawnorm_codegen::Entity, Debug, Clone)]
+pub struct Author {
+    #[key_noinsert_noupdate]
+    id: i32,
+    name: String,
+}
+
+#[derive(dawnorm_codegen::Entity, Debug)]
+pub struct Comment {
+    #[key_noinsert_noupdate]
+    id: i32,
+    post_id: i32,
+    text: String,
+    #[dawnorm(soft_delete)]
+    deleted_at: Option<std::time::SystemTime>,
+    #[dawnorm(created_at)]
+    created_at: std::time::SystemTime,
+    #[dawnorm(updated_at)]
+    updated_at: std::time::SystemTime,
+}
+
+#[derive(dawnorm_codegen::Entity, Debug)]
+pub struct Metric<V> {
+    #[key_noinsert_noupdate]
+    id: i32,
+    author_id: i32,
+    name: String,
+    value: V,
+    #[dawnorm(belongs_to = Author, table = "authors")]
+    author: Option<Author>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Default, PartialEq)]
+pub struct Preferences {
+    theme: String,
+    notifications: bool,
+}
+
+#[derive(dawnorm_codegen::Entity, Debug)]
+pub struct AuthorSetting {
+    #[key_noinsert_noupdate]
+    id: i32,
+    author_id: i32,
+    #[dawnorm(json)]
+    preferences: Preferences,
+    #[dawnorm(json)]
+    tags: Option<Vec<String>>,
+}
+
+#[derive(dawnorm_codegen::Entity, Debug)]
+#[dawnorm(view)]
+pub struct AuthorPostCount {
+    author_id: i32,
+    name: String,
+    posts: i64,
+}
+
+#[derive(dawnorm_codegen::Projection, Debug)]
+pub struct PostSummary {
+    id: i32,
+    #[dawnorm(expr = "length(title)")]
+    title_length: i32,
+}
+
+#[derive(dawnorm_codegen::FromRow, Debug)]
+pub struct PostRank {
+    #[dawnorm(rename = "id")]
+    post_id: i32,
+    rank: i64,
+}
+
+#[derive(dawnorm_codegen::Projection, Debug)]
+pub struct TitleCount {
+    title: String,
+    #[dawnorm(expr = "count(*)")]
+    posts: i64,
 }
 
 pub struct DbContex {
     client: Arc<Client>,
+    changes: ChangeTracker,
+    identity_map: IdentityMap,
 }
 
 impl DbContex {
     dawnorm::dbset!(posts, Post);
+    dawnorm::dbset!(authors, Author, identity_map);
+    dawnorm::dbset!(comments, Comment);
+    dawnorm::dbset!(author_post_counts, AuthorPostCount);
+    dawnorm::dbset!(metrics, Metric<f64>);
+    dawnorm::dbset!(author_settings, AuthorSetting);
+    dawnorm::raw_sql!();
+    dawnorm::change_tracker!(changes);
 }
 
-#[tokio::main]
-async fn main() {
-    color_eyre::install().unwrap();
+async fn connect() -> Client {
     let (client, connection) =
         tokio_postgres::connect("host=localhost user=postgres password=postgrespw", NoTls)
             .await
@@ -35,9 +136,19 @@ async fn main() {
         }
     });
 
+    client
+}
+
+#[tokio::main]
+async fn main() {
+    color_eyre::install().unwrap();
+    let client = connect().await;
+    // transactions get a connection of their own, queries of the dbsets can't end up in them
+    let mut tx_client = connect().await;
+
     migrations::build_migrator().migrate(&client).await.unwrap();
 
-    let ctx = DbContex { client: client.into() };
+    let ctx = DbContex { client: client.into(), changes: ChangeTracker::new(), identity_map: IdentityMap::new() };
     let p = ctx.posts()
         .filter(format!("{} = $1 or {} = $2", PostFields::title(), PostFields::id()), parms!["test", 4])
         .order_by(PostFields::id(), Ordering::ASC)
@@ -45,10 +156,115 @@ async fn main() {
         //.take(1)
         .to_vec().await.unwrap();
     dbg!(p);
+
+    let summaries = ctx.posts().order_by(PostFields::id(), Ordering::ASC).select::<PostSummary>().await.unwrap();
+    for summary in summaries {
+        println!("post {} has a title of length {}", summary.id, summary.title_length);
+    }
+
+    let titles = ctx.posts().select_tuple((PostFields::id(), PostFields::title())).await.unwrap();
+    dbg!(titles);
+
+    let ranks = ctx.query_as::<PostRank>(
+        "SELECT id, rank() OVER (ORDER BY length(title) DESC) AS rank FROM posts WHERE id > $1",
+        parms![0]
+    ).await.unwrap();
+    for rank in ranks {
+        println!("post {} is ranked {}", rank.post_id, rank.rank);
+    }
+
+    let long_posts = ctx.posts()
+        .from_sql("WITH long AS (SELECT * FROM posts WHERE length(title) > $1) SELECT * FROM long", parms![8])
+        .await.unwrap();
+    dbg!(long_posts);
+
+    let count = ctx.posts().filter(format!("{} > $1", PostFields::id()), parms![2]).count().await.unwrap();
+    let id_sum = ctx.posts().sum(PostFields::id()).await.unwrap();
+    let id_avg = ctx.posts().avg(PostFields::id()).await.unwrap();
+    let first_title = ctx.posts().min(PostFields::title()).await.unwrap();
+    let last_id = ctx.posts().max(PostFields::id()).await.unwrap();
+    dbg!(count, id_sum, id_avg, first_title, last_id);
+
+    let title_counts = ctx.posts()
+        .group_by(PostFields::title())
+        .having(format!("{} >= $1", dawnorm::count()), parms![1i64])
+        .order_by(PostFields::title(), Ordering::ASC)
+        .select::<TitleCount>().await.unwrap();
+    for title_count in title_counts {
+        println!("{} posts titled {}", title_count.posts, title_count.title);
+    }
+
+    let bodies = ctx.posts()
+        .filter(format!("{} > $1", PostFields::id()), parms![0])
+        .group_by(PostFields::body())
+        .having(format!("{} > $2", dawnorm::count()), parms![0i64])
+        .select_tuple((PostFields::body(), dawnorm::count(), PostFields::id().max()))
+        .await.unwrap();
+    dbg!(bodies);
+
+    let mut cursor = None;
+    loop {
+        let page = ctx.posts()
+            .order_by(PostFields::id(), Ordering::DESC)
+            .page_after(cursor.as_ref(), 3)
+            .await.unwrap();
+        println!("page with posts {:?}", page.items.iter().map(|x| x.id).collect::<Vec<i32>>());
+        match page.next {
+            Some(next) => cursor = Some(next),
+            None => break
+        }
+    }
+
+    for page in 0..4 {
+        let paged = ctx.posts()
+            .filter(format!("{} > $1", PostFields::id()), parms![1])
+            .order_by(PostFields::id(), Ordering::ASC)
+            .paginate(page, 2)
+            .await.unwrap();
+        println!(
+            "page {} of {} ({} posts): {:?}",
+            paged.page, paged.total_pages, paged.total, paged.items.iter().map(|x| x.id).collect::<Vec<i32>>()
+        );
+    }
+
+    let transaction = tx_client.transaction().await.unwrap();
+    let job = ctx.posts()
+        .in_transaction(&transaction)
+        .order_by(PostFields::id(), Ordering::ASC)
+        .for_update()
+        .skip_locked()
+        .first().await.unwrap();
+    println!("locked post {}", job.id);
+    transaction.commit().await.unwrap();
+
+    let renamed = ctx.posts()
+        .filter(format!("{} <= $1", PostFields::id()), parms![2])
+        .update_set()
+        .set(PostFields::body(), Some("rewritten".to_string()))
+        .set_expr(PostFields::title(), "upper(title)")
+        .exec_returning().await.unwrap();
+    println!("renamed {:?}", renamed.iter().map(|x| x.title.as_str()).collect::<Vec<&str>>());
+
+    let invalid = ctx.posts()
+        .filter(format!("{} <= $1", PostFields::id()), parms![2])
+        .update_set()
+        .set_expr("likes", "likes + 1")
+        .exec().await;
+    println!("{}", invalid.unwrap_err());
+
+    let touched = ctx.execute_sql("UPDATE posts SET body = body WHERE id = $1", parms![1]).await.unwrap();
+    dbg!(touched);
     
-    let i = Post { id: 0, title: "this is cool".into(), body: Some("this is the body".into()) };
+    let author = ctx.authors().insert(Author { id: 0, name: "angus".into() }).await.unwrap();
+    let i = Post { id: 0, title: "this is cool".into(), body: Some("this is the body".into()), author_id: Some(author.id), views: None, state: PostState::Draft, author: None, comments: Vec::new() };
     let ri = ctx.posts().insert(i).await.unwrap();
     dbg!(&ri);
+    let popular = ctx.posts().insert(Post { id: 0, title: "popular".into(), body: None, author_id: None, views: Some(100), state: PostState::Published, author: None, comments: Vec::new() }).await.unwrap();
+    println!("{} views by default, {} views when set", ri.views.unwrap(), popular.views.unwrap());
+    let published = ctx.posts().filter(format!("{} = $1", PostFields::state()), parms![PostState::Published]).count().await.unwrap();
+    println!("{} published posts, {:?} by default", published, ri.state);
+    let deleted = ctx.posts().delete_by_key(popular.id).await.unwrap();
+    println!("deleted popular: {}, still exists: {}", deleted, ctx.posts().exists_by_key(popular.id).await.unwrap());
     
     let mut to_update = ctx.posts().filter_pk(parms!(5)).first().await.unwrap();
     dbg!(&to_update);
@@ -56,6 +272,127 @@ async fn main() {
     let updated = ctx.posts().update(to_update).await.unwrap();
     dbg!(updated);
 
+    let mut tracked = ctx.posts().filter_pk(parms!(4)).first_tracked().await.unwrap();
+    tracked.title = "only the title changes".into();
+    let tracked = ctx.posts().update_tracked(tracked).await.unwrap();
+    println!("{} keeps body {:?}", tracked.title, tracked.body);
+
+    let tracked_author = ctx.authors().insert(Author { id: 0, name: "tracked".into() }).await.unwrap();
+    let mut retitled = ctx.posts().filter_pk(parms!(3)).first_tracked().await.unwrap();
+    retitled.title = "saved with save_changes".into();
+    ctx.changes.add(ctx.authors(), Author { id: 0, name: "added".into() });
+    ctx.changes.update(ctx.posts(), retitled);
+    ctx.changes.remove(ctx.authors(), &tracked_author);
+    // read again before saving, which puts the author back in the identity map
+    ctx.authors().find(tracked_author.id).await.unwrap();
+    println!("save_changes affected {} rows", ctx.save_changes(&mut tx_client).await.unwrap());
+    println!("removed author is {:?} after save_changes", ctx.authors().find(tracked_author.id).await.unwrap().map(|x| x.id));
+
+    let cached = ctx.authors().find(author.id).await.unwrap().unwrap();
+    let transaction = tx_client.transaction().await.unwrap();
+    ctx.authors().in_transaction(&transaction).update(Author { name: "renamed in a transaction".into(), ..cached }).await.unwrap();
+    transaction.commit().await.unwrap();
+    println!("identity map returns {} after the commit", ctx.authors().find(author.id).await.unwrap().unwrap().name);
+
+    let cached = ctx.authors().filter_pk(parms!(author.id)).first().await.unwrap();
+    ctx.execute_sql("UPDATE authors SET name = 'changed behind the map' WHERE id = $1", parms![author.id]).await.unwrap();
+    let again = ctx.authors().filter_pk(parms!(author.id)).first().await.unwrap();
+    println!("identity map returned {} twice, caching {} authors", again.name, ctx.identity_map.len());
+    ctx.identity_map.clear();
+    let reloaded = ctx.authors().find(author.id).await.unwrap().unwrap();
+    println!("{} became {} after clearing the map", cached.name, reloaded.name);
+
+    ctx.author_post_counts().refresh_materialized_view(true).await.unwrap();
+    let post_counts = ctx.author_post_counts().order_by(AuthorPostCountFields::author_id(), Ordering::ASC).to_vec().await.unwrap();
+    for post_count in post_counts {
+        println!("author {} ({}) wrote {} posts", post_count.name, post_count.author_id, post_count.posts);
+    }
+
+    ctx.metrics().insert(Metric { id: 0, author_id: author.id, name: "rating".into(), value: 4.5, author: None }).await.unwrap();
+    let metric = ctx.metrics()
+        .filter(format!("{} > $1", MetricFields::<f64>::value()), parms![4.0])
+        .include(Metric::author)
+        .first().await.unwrap();
+    println!("{} of {} is {}", metric.name, metric.author.map(|x| x.name).unwrap_or_default(), metric.value);
+
+    let preferences = Preferences { theme: "dark".into(), notifications: true };
+    ctx.author_settings().insert(AuthorSetting { id: 0, author_id: author.id, preferences, tags: Some(vec!["rust".into()]) }).await.unwrap();
+    ctx.author_settings().insert(AuthorSetting { id: 0, author_id: author.id, preferences: Preferences::default(), tags: None }).await.unwrap();
+    let mut dark = ctx.author_settings()
+        .filter_json_text(AuthorSettingFields::preferences(), "theme", "dark")
+        .first_tracked().await.unwrap();
+    dark.preferences.theme = "light".into();
+    let light = ctx.author_settings().update_tracked(dark).await.unwrap();
+    let notified = ctx.author_settings()
+        .filter("author_id = $1", parms![author.id])
+        .filter_json_contains(AuthorSettingFields::preferences(), serde_json::json!({ "notifications": true }))
+        .count().await.unwrap();
+    println!("{:?} tagged {:?}, {} settings with notifications", light.preferences, light.tags, notified);
+    ctx.author_settings()
+        .filter(format!("{} = $1", AuthorSettingFields::id()), parms![light.id])
+        .update_set()
+        .set(AuthorSettingFields::preferences(), dawnorm::Json(Preferences { theme: "dark".into(), notifications: false }))
+        .exec().await.unwrap();
+    let themes = ctx.author_settings()
+        .filter_json_text(AuthorSettingFields::preferences(), "theme", "dark")
+        .select_tuple((AuthorSettingFields::preferences(),))
+        .await.unwrap();
+    println!("dark themes {:?}", themes.iter().map(|x| &x.0.0).collect::<Vec<&Preferences>>());
+
+    let found = ctx.posts().find_many(vec![1, 2, 1000]).await.unwrap();
+    println!("found posts {:?}, post 1000 is {:?}", found.iter().map(|x| x.id).collect::<Vec<i32>>(), ctx.posts().find(1000).await.unwrap());
+
+    let with_authors = ctx.posts()
+        .inner_join(ctx.authors(), "posts.author_id = authors.id")
+        .order_by("posts.id", Ordering::ASC)
+        .to_vec().await.unwrap();
+    for (post, author) in with_authors {
+        println!("{} was written by {}", post.title, author.name);
+    }
+
+    let maybe_authors = ctx.posts()
+        .left_join(ctx.authors(), "posts.author_id = authors.id")
+        .filter("posts.id <= $1", parms![3])
+        .to_vec().await.unwrap();
+    for (post, author) in maybe_authors {
+        println!("{} has author {:?}", post.id, author.map(|x| x.id));
+    }
+
+    let next_posts = ctx.posts().alias("p")
+        .left_join(ctx.posts().alias("next"), "next.id = p.id + 1")
+        .filter("p.id <= $1", parms![2])
+        .order_by("p.id", Ordering::ASC)
+        .to_vec().await.unwrap();
+    for (post, next) in next_posts {
+        println!("{} is followed by {:?}", post.id, next.map(|x| x.id));
+    }
+
+    ctx.comments().insert(Comment { id: 0, post_id: ri.id, text: "first!".into(), deleted_at: None, created_at: std::time::UNIX_EPOCH, updated_at: std::time::UNIX_EPOCH }).await.unwrap();
+    let second = ctx.comments().insert(Comment { id: 0, post_id: ri.id, text: "second".into(), deleted_at: None, created_at: std::time::UNIX_EPOCH, updated_at: std::time::UNIX_EPOCH }).await.unwrap();
+    let spam = ctx.comments().insert(Comment { id: 0, post_id: ri.id, text: "spam".into(), deleted_at: None, created_at: std::time::UNIX_EPOCH, updated_at: std::time::UNIX_EPOCH }).await.unwrap();
+    ctx.comments().delete(&spam).await.unwrap();
+    ctx.comments().filter(format!("{} = $1", CommentFields::id()), parms![second.id]).exec_delete::<i32>().await.unwrap();
+    println!(
+        "{} comments, {} with deleted, {} deleted",
+        ctx.comments().count().await.unwrap(),
+        ctx.comments().with_deleted().count().await.unwrap(),
+        ctx.comments().only_deleted().count().await.unwrap()
+    );
+    ctx.comments().filter(format!("{} = $1", CommentFields::id()), parms![second.id]).restore().await.unwrap();
+    let edited = ctx.comments().update(Comment { text: "second, edited".into(), ..second }).await.unwrap();
+    println!("comment created at {:?} and updated at {:?}", edited.created_at, edited.updated_at);
+    let loaded = ctx.posts()
+        .filter(format!("{} = $1", PostFields::id()), parms![ri.id])
+        .include(Post::author)
+        .include(Post::comments)
+        .first().await.unwrap();
+    println!(
+        "{} by {:?} has comments {:?}",
+        loaded.title,
+        loaded.author.map(|x| x.name),
+        loaded.comments.iter().map(|x| x.text.as_str()).collect::<Vec<&str>>()
+    );
+
     ctx.posts().delete(&ri).await.unwrap();
 }
  
\ No newline at end of file
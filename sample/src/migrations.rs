@@ -43,7 +43,6 @@ use dawnorm::migration::Migrator;
 /// | `uuid::Uuid`                    | UUID                                |
 /// | `bit_vec::BitVec`               | BIT, VARBIT                         |
 /// | `eui48::MacAddress`             | MACADDR                             |
-
 pub fn build_migrator() -> Migrator {
     Migrator::new().add_up(
         "initial-migration",
@@ -61,5 +60,65 @@ pub fn build_migrator() -> Migrator {
         title TEXT NOT NULL,
         body TEXT
     );"#,
+    ).add_up(
+        "add-authors",
+        r#"
+    CREATE TABLE authors (
+        id SERIAL PRIMARY KEY,
+        name TEXT NOT NULL
+    );
+    ALTER TABLE posts ADD COLUMN author_id INT REFERENCES authors(id);"#,
+    ).add_up(
+        "add-comments",
+        r#"
+    CREATE TABLE comments (
+        id SERIAL PRIMARY KEY,
+        post_id INT NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+        text TEXT NOT NULL
+    );"#,
+    ).add_up(
+        "soft-delete-comments",
+        r#"
+    ALTER TABLE comments ADD COLUMN deleted_at TIMESTAMPTZ;"#,
+    ).add_up(
+        "timestamp-comments",
+        r#"
+    ALTER TABLE comments ADD COLUMN created_at TIMESTAMPTZ NOT NULL DEFAULT now();
+    ALTER TABLE comments ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT now();"#,
+    ).add_up(
+        "post-views",
+        r#"
+    ALTER TABLE posts ADD COLUMN views INT NOT NULL DEFAULT 0;"#,
+    ).add_up(
+        "author-post-counts",
+        r#"
+    CREATE MATERIALIZED VIEW author_post_counts AS
+        SELECT authors.id AS author_id, authors.name, count(posts.id) AS posts
+        FROM authors LEFT JOIN posts ON posts.author_id = authors.id
+        GROUP BY authors.id, authors.name;
+    CREATE UNIQUE INDEX author_post_counts_author_id ON author_post_counts (author_id);"#,
+    ).add_up(
+        "add-metrics",
+        r#"
+    CREATE TABLE metrics (
+        id SERIAL PRIMARY KEY,
+        author_id INT NOT NULL REFERENCES authors(id),
+        name TEXT NOT NULL,
+        value DOUBLE PRECISION NOT NULL
+    );"#,
+    ).add_up(
+        "post-state",
+        r#"
+    CREATE TYPE post_state AS ENUM ('draft', 'published', 'archived');
+    ALTER TABLE posts ADD COLUMN state post_state NOT NULL DEFAULT 'draft';"#,
+    ).add_up(
+        "add-author-settings",
+        r#"
+    CREATE TABLE author_settings (
+        id SERIAL PRIMARY KEY,
+        author_id INT NOT NULL REFERENCES authors(id),
+        preferences JSONB NOT NULL,
+        tags JSONB
+    );"#,
     )
 }